@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::reader::RequestReader;
+use crate::response::{Output, Response};
+use crate::server::{send_read_error, Server};
+
+pub async fn run(server: Arc<Server>) {
+    let listener = TcpListener::bind("127.0.0.1:4221").await.unwrap();
+    println!("Using the async runtime");
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let server = Arc::clone(&server);
+                tokio::spawn(async move { handle_connection(stream, &server).await });
+            }
+            Err(e) => {
+                println!("error: {}", e);
+            }
+        }
+    }
+}
+
+/// Async counterpart of `Server::handle_connection`. Responses are collected in
+/// a buffer by the handler and written once it completes, except for streamed
+/// bodies, which are copied to the socket as they are read.
+pub async fn handle_connection(mut stream: TcpStream, server: &Server) {
+    let config = &server.config;
+    let mut reader = RequestReader::new(config.limits);
+    loop {
+        let read = reader.read_request_async(&mut stream);
+        let read = if config.idle_timeout.is_zero() {
+            read.await
+        } else {
+            match tokio::time::timeout(config.idle_timeout, read).await {
+                Ok(read) => read,
+                Err(_) => return,
+            }
+        };
+
+        let request = match read {
+            Ok(request) => request,
+            Err(error) => {
+                let mut response = Response::buffered();
+                send_read_error(error, &mut response);
+                let _ = write_response(&mut stream, response).await;
+                return;
+            }
+        };
+
+        let mut response = Response::buffered();
+        let mut response = match server.dispatch(request, &mut response) {
+            Some((handler, request)) => handler.call_async(request, response).await,
+            None => response,
+        };
+        if !response.is_sent() {
+            response.send();
+        }
+
+        match write_response(&mut stream, response).await {
+            Ok(true) => {}
+            _ => return,
+        }
+    }
+}
+
+/// How many pieces of a streamed body may wait for the socket at once.
+const STREAM_BACKLOG: usize = 4;
+
+/// Writes a sent response to the socket and returns whether the connection
+/// stays open. A pending stream is read on a blocking thread and handed over
+/// through a bounded channel, so only a few pieces are in memory at a time.
+async fn write_response(stream: &mut TcpStream, mut response: Response) -> std::io::Result<bool> {
+    stream.write_all(&response.take_buffer()).await?;
+    if response.has_pending_stream() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(STREAM_BACKLOG);
+        let pump = tokio::task::spawn_blocking(move || {
+            response.send_stream(Output::Channel(sender));
+            response
+        });
+        // Returning early drops the receiver, which stops the pump at its
+        // next write.
+        while let Some(piece) = receiver.recv().await {
+            stream.write_all(&piece).await?;
+        }
+        response = pump.await.map_err(std::io::Error::other)?;
+    }
+    stream.flush().await?;
+    Ok(response.keep_alive)
+}
@@ -0,0 +1,374 @@
+use std::io::{self, Read, Write};
+
+use bytes::Bytes;
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use crate::conditional;
+use crate::request::Request;
+use crate::request::ENCODINGS;
+use crate::response::{Body, Response};
+
+/// Content codings the server can produce, in order of preference when a
+/// client rates several equally. `br` is understood in `Accept-Encoding` but
+/// never chosen, since no brotli encoder is available to the build.
+pub const AVAILABLE: [ENCODINGS; 2] = [ENCODINGS::GZIP, ENCODINGS::DEFLATE];
+
+/// Response compression policy. Bodies shorter than `min_size` are sent as
+/// is, since the framing overhead outweighs the saving on small payloads.
+#[derive(Clone, Copy, Debug)]
+pub struct Compression {
+    pub min_size: usize,
+    /// flate2 level, 0 (none) to 9 (best).
+    pub level: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: 1024,
+            level: 6,
+        }
+    }
+}
+
+/// The codings listed in a request's `Accept-Encoding`, with their q-values.
+#[derive(Clone, Debug, Default)]
+pub struct AcceptEncoding {
+    entries: Vec<(String, f32)>,
+}
+
+impl AcceptEncoding {
+    /// Parses e.g. `gzip;q=0.8, deflate, *;q=0`. Entries with an unreadable
+    /// q-value are dropped.
+    pub fn parse(values: &[&str]) -> AcceptEncoding {
+        let entries = values
+            .iter()
+            .filter_map(|value| {
+                let mut parts = value.split(';');
+                let coding = parts.next()?.trim().to_lowercase();
+                let mut quality = 1.0;
+                for parameter in parts {
+                    if let Some((name, value)) = parameter.split_once('=') {
+                        if name.trim().eq_ignore_ascii_case("q") {
+                            quality = value.trim().parse::<f32>().ok()?;
+                        }
+                    }
+                }
+                Some((coding, quality.clamp(0.0, 1.0)))
+            })
+            .collect();
+        AcceptEncoding { entries }
+    }
+
+    fn quality(&self, coding: &str) -> Option<f32> {
+        self.entries
+            .iter()
+            .find(|(name, _)| name == coding)
+            .or_else(|| self.entries.iter().find(|(name, _)| name == "*"))
+            .map(|(_, quality)| *quality)
+    }
+
+    /// The coding to use among `available`, or `None` for identity. Codings
+    /// the client did not list are unacceptable unless `*` covers them, and an
+    /// explicitly listed `identity` wins over anything rated lower.
+    pub fn preferred(&self, available: &[ENCODINGS]) -> Option<ENCODINGS> {
+        let mut best: Option<(ENCODINGS, f32)> = None;
+        for coding in available {
+            let quality = self.quality(&coding.to_string()).unwrap_or(0.0);
+            if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((*coding, quality));
+            }
+        }
+
+        let (coding, quality) = best?;
+        let identity = self
+            .entries
+            .iter()
+            .find(|(name, _)| name == "identity")
+            .map(|(_, quality)| *quality);
+        match identity {
+            Some(identity) if identity > quality => None,
+            _ => Some(coding),
+        }
+    }
+}
+
+/// Whether a body of this media type is worth compressing. Images, audio,
+/// video and archives are already compressed.
+pub fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "application/x-www-form-urlencoded"
+                | "image/svg+xml"
+        )
+}
+
+/// Compresses the body of `response` according to its policy and the
+/// client's `Accept-Encoding`. Responses that already carry a
+/// `Content-Encoding`, have no body, or have an incompressible type are left
+/// alone, as are partial responses, whose ranges refer to the uncompressed
+/// bytes. A compressed stream is sent chunked, since its length is unknown.
+/// The compressed body is a representation of its own, so it gets its own
+/// entity tag and does not offer ranges, which are only served uncompressed.
+pub fn compress(response: &mut Response) -> io::Result<()> {
+    let policy = match response.compression {
+        Some(policy) => policy,
+        None => return Ok(()),
+    };
+    let code = response.status.code();
+    if code < 200
+        || code == 204
+        || code == 304
+        || response.headers.contains("Content-Encoding")
+        || response.headers.contains("Content-Range")
+        || !response.headers.content_type().is_some_and(is_compressible)
+    {
+        return Ok(());
+    }
+
+    if !response.headers.has_token("Vary", "Accept-Encoding") {
+        response.headers.append("Vary", "Accept-Encoding");
+    }
+    if response
+        .body
+        .len()
+        .is_some_and(|length| length < policy.min_size)
+    {
+        return Ok(());
+    }
+    let coding = match response.accept_encoding.preferred(&AVAILABLE) {
+        Some(coding) => coding,
+        None => return Ok(()),
+    };
+
+    let level = flate2::Compression::new(policy.level.min(9));
+    let body = std::mem::replace(&mut response.body, Body::Binary(Vec::new()));
+    response.body = match (body, coding) {
+        (Body::Stream(reader), ENCODINGS::GZIP) => {
+            response.headers.remove("Content-Length");
+            Body::stream(flate2::read::GzEncoder::new(reader, level))
+        }
+        (Body::Stream(reader), _) => {
+            response.headers.remove("Content-Length");
+            Body::stream(flate2::read::ZlibEncoder::new(reader, level))
+        }
+        (Body::Text(text), coding) => Body::Binary(encode(text.as_bytes(), coding, level)?),
+        (Body::Binary(data), coding) => Body::Binary(encode(&data, coding, level)?),
+    };
+    response
+        .headers
+        .insert("Content-Encoding", coding.to_string());
+    if let Some(etag) = response.headers.get("ETag") {
+        let etag = conditional::coded_etag(etag, &coding.to_string());
+        response.headers.insert("ETag", etag);
+    }
+    response.headers.remove("Accept-Ranges");
+    Ok(())
+}
+
+fn encode(data: &[u8], coding: ENCODINGS, level: flate2::Compression) -> io::Result<Vec<u8>> {
+    match coding {
+        ENCODINGS::GZIP => {
+            let mut encoder = GzEncoder::new(Vec::new(), level);
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        _ => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), level);
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// A `Content-Encoding` the server cannot decode.
+    Unsupported(String),
+    /// The decoded body would exceed the size limit.
+    TooLarge,
+    Corrupt,
+}
+
+/// Undoes the `Content-Encoding` of a request body so handlers always see
+/// the original bytes. Codings are removed in the reverse of the order they
+/// were applied, and at most `limit` decoded bytes are produced, which stops
+/// a small compressed upload from inflating without bound.
+pub fn decompress(request: &mut Request, limit: usize) -> Result<(), DecodeError> {
+    let codings: Vec<String> = request
+        .headers
+        .get_list("Content-Encoding")
+        .into_iter()
+        .map(|coding| coding.to_lowercase())
+        .collect();
+    if codings.is_empty() {
+        return Ok(());
+    }
+
+    let mut body = request.body.to_vec();
+    for coding in codings.iter().rev() {
+        body = match coding.as_str() {
+            "identity" => body,
+            "gzip" | "x-gzip" => decode(MultiGzDecoder::new(body.as_slice()), limit)?,
+            // `deflate` is meant to be zlib-wrapped, but some clients send a
+            // raw deflate stream instead.
+            "deflate" => match decode(ZlibDecoder::new(body.as_slice()), limit) {
+                Err(DecodeError::Corrupt) => decode(DeflateDecoder::new(body.as_slice()), limit)?,
+                decoded => decoded?,
+            },
+            _ => return Err(DecodeError::Unsupported(coding.clone())),
+        };
+    }
+
+    request.headers.remove("Content-Encoding");
+    request
+        .headers
+        .insert("Content-Length", body.len().to_string());
+    request.body = Bytes::from(body);
+    Ok(())
+}
+
+fn decode<R: Read>(decoder: R, limit: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut decoded = Vec::new();
+    decoder
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|_| DecodeError::Corrupt)?;
+    if decoded.len() > limit {
+        return Err(DecodeError::TooLarge);
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn preferred(header: &str) -> Option<ENCODINGS> {
+        let values: Vec<&str> = header.split(',').map(|value| value.trim()).collect();
+        AcceptEncoding::parse(&values).preferred(&AVAILABLE)
+    }
+
+    #[test]
+    fn prefers_highest_quality() {
+        assert_eq!(preferred("gzip"), Some(ENCODINGS::GZIP));
+        assert_eq!(preferred("deflate"), Some(ENCODINGS::DEFLATE));
+        assert_eq!(
+            preferred("gzip;q=0.5, deflate;q=0.8"),
+            Some(ENCODINGS::DEFLATE)
+        );
+        assert_eq!(preferred("GZIP;Q=0.9"), Some(ENCODINGS::GZIP));
+    }
+
+    #[test]
+    fn breaks_ties_in_server_order() {
+        assert_eq!(preferred("deflate, gzip"), Some(ENCODINGS::GZIP));
+    }
+
+    #[test]
+    fn never_chooses_unavailable_codings() {
+        assert_eq!(preferred("br"), None);
+        assert_eq!(preferred("br, deflate;q=0.1"), Some(ENCODINGS::DEFLATE));
+    }
+
+    #[test]
+    fn refuses_unlisted_and_zero_rated_codings() {
+        assert_eq!(preferred(""), None);
+        assert_eq!(preferred("gzip;q=0"), None);
+        assert_eq!(preferred("gzip;q=0, deflate;q=0"), None);
+    }
+
+    #[test]
+    fn wildcard_covers_unlisted_codings() {
+        assert_eq!(preferred("*"), Some(ENCODINGS::GZIP));
+        assert_eq!(preferred("gzip;q=0, *"), Some(ENCODINGS::DEFLATE));
+        assert_eq!(preferred("*;q=0"), None);
+    }
+
+    #[test]
+    fn identity_rated_higher_wins() {
+        assert_eq!(preferred("identity, gzip;q=0.5"), None);
+        assert_eq!(preferred("identity;q=0.5, gzip"), Some(ENCODINGS::GZIP));
+    }
+
+    #[test]
+    fn drops_unreadable_qualities() {
+        assert_eq!(
+            preferred("gzip;q=high, deflate;q=0.2"),
+            Some(ENCODINGS::DEFLATE)
+        );
+    }
+
+    #[test]
+    fn classifies_compressible_types() {
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("application/ld+json"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/zip"));
+    }
+
+    fn upload(coding: &str, body: Vec<u8>) -> Request {
+        let mut request = Request::new(&format!(
+            "POST /upload HTTP/1.1\r\nContent-Encoding: {}\r\n\r\n",
+            coding
+        ));
+        request.body = Bytes::from(body);
+        request
+    }
+
+    #[test]
+    fn decompresses_request_bodies() {
+        let level = flate2::Compression::default();
+        let gzip = encode(b"hello", ENCODINGS::GZIP, level).unwrap();
+        let mut request = upload("gzip", gzip);
+        decompress(&mut request, 1024).unwrap();
+        assert_eq!(&request.body[..], b"hello");
+        assert_eq!(request.headers.get("Content-Length"), Some("5"));
+        assert!(!request.headers.contains("Content-Encoding"));
+
+        let zlib = encode(b"hello", ENCODINGS::DEFLATE, level).unwrap();
+        let mut request = upload("deflate", zlib);
+        decompress(&mut request, 1024).unwrap();
+        assert_eq!(&request.body[..], b"hello");
+    }
+
+    #[test]
+    fn limits_decompressed_size() {
+        let bomb = encode(&[0; 4096], ENCODINGS::GZIP, flate2::Compression::best()).unwrap();
+        let mut request = upload("gzip", bomb);
+        assert!(matches!(
+            decompress(&mut request, 1024),
+            Err(DecodeError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_and_corrupt_bodies() {
+        let mut request = upload("br", b"abc".to_vec());
+        assert!(matches!(
+            decompress(&mut request, 1024),
+            Err(DecodeError::Unsupported(coding)) if coding == "br"
+        ));
+        let mut request = upload("gzip", b"not gzip".to_vec());
+        assert!(matches!(
+            decompress(&mut request, 1024),
+            Err(DecodeError::Corrupt)
+        ));
+    }
+}
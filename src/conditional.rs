@@ -0,0 +1,320 @@
+use std::fs::Metadata;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::compression;
+use crate::http_date;
+use crate::request::{HTTPRequestMethod, Request};
+use crate::response::{Body, HTTPResponseStatus, Response};
+
+/// What a client can make a request conditional on: the current entity tag
+/// and modification time of a resource, or `None` for both when the resource
+/// does not exist.
+#[derive(Clone, Debug, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// An entity tag built from the file's size and modification time, which
+    /// changes whenever the file is rewritten without reading its contents.
+    pub fn from_metadata(metadata: &Metadata) -> Validators {
+        let modified = metadata.modified().ok();
+        let nanos = modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        Validators {
+            etag: Some(format!("\"{:x}-{:x}\"", metadata.len(), nanos)),
+            last_modified: modified.map(http_date::truncate),
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+
+    /// Adds `ETag` and `Last-Modified` to a response.
+    pub fn apply(&self, response: &mut Response) {
+        if let Some(etag) = &self.etag {
+            response.headers.insert("ETag", etag.clone());
+        }
+        if let Some(last_modified) = self.last_modified {
+            response
+                .headers
+                .insert("Last-Modified", http_date::format(last_modified));
+        }
+    }
+
+    /// Whether an `If-Range` validator still describes this resource. Entity
+    /// tags must match strongly, and dates exactly.
+    pub fn matches_if_range(&self, validator: &str) -> bool {
+        if validator.starts_with('"') {
+            return self
+                .etag
+                .as_deref()
+                .is_some_and(|etag| strong_match(validator, etag));
+        }
+        match (http_date::parse(validator), self.last_modified) {
+            (Some(date), Some(modified)) => date == modified,
+            _ => false,
+        }
+    }
+}
+
+/// Evaluates the request's preconditions in the order RFC 9110 gives them.
+/// Returns the status to answer with instead of handling the request:
+/// `304 Not Modified` when a GET or HEAD finds the client's copy current,
+/// `412 Precondition Failed` when any other precondition fails.
+pub fn evaluate(request: &Request, validators: &Validators) -> Option<HTTPResponseStatus> {
+    let headers = &request.headers;
+    let read = matches!(
+        request.get_method(),
+        HTTPRequestMethod::GET | HTTPRequestMethod::HEAD
+    );
+
+    if headers.contains("If-Match") {
+        let matched = match &validators.etag {
+            Some(etag) => list_matches(headers.get_list("If-Match"), |tag| strong_match(tag, etag)),
+            None => false,
+        };
+        if !matched {
+            return Some(HTTPResponseStatus::PRECONDITIONFAILED);
+        }
+    } else if let Some(since) = headers
+        .get("If-Unmodified-Since")
+        .and_then(http_date::parse)
+    {
+        if validators
+            .last_modified
+            .is_some_and(|modified| modified > since)
+        {
+            return Some(HTTPResponseStatus::PRECONDITIONFAILED);
+        }
+    }
+
+    if headers.contains("If-None-Match") {
+        let matched = validators.exists()
+            && list_matches(headers.get_list("If-None-Match"), |tag| {
+                validators
+                    .etag
+                    .as_deref()
+                    .is_some_and(|etag| weak_match(tag, etag))
+            });
+        if matched {
+            return Some(if read {
+                HTTPResponseStatus::NOTMODIFIED
+            } else {
+                HTTPResponseStatus::PRECONDITIONFAILED
+            });
+        }
+    } else if read {
+        if let Some(since) = headers.get("If-Modified-Since").and_then(http_date::parse) {
+            if validators
+                .last_modified
+                .is_some_and(|modified| modified <= since)
+            {
+                return Some(HTTPResponseStatus::NOTMODIFIED);
+            }
+        }
+    }
+
+    None
+}
+
+/// `*` matches any current representation; otherwise one listed tag must.
+fn list_matches<F: Fn(&str) -> bool>(tags: Vec<&str>, matches: F) -> bool {
+    tags.iter().any(|tag| *tag == "*" || matches(tag))
+}
+
+fn strong_match(tag: &str, etag: &str) -> bool {
+    !tag.starts_with("W/") && !etag.starts_with("W/") && tag == etag
+}
+
+/// Weak comparison also pairs the tag of a compressed copy with the tag of
+/// the representation it was made from, so a cached gzip response can still
+/// be revalidated.
+fn weak_match(tag: &str, etag: &str) -> bool {
+    let tag = tag.trim_start_matches("W/");
+    let etag = etag.trim_start_matches("W/");
+    tag == etag
+        || compression::AVAILABLE
+            .iter()
+            .any(|coding| tag == coded_etag(etag, &coding.to_string()))
+}
+
+/// The entity tag for `etag`'s representation compressed with `coding`,
+/// e.g. `"1f-3a"` becomes `"1f-3a-gzip"`.
+pub fn coded_etag(etag: &str, coding: &str) -> String {
+    match etag.strip_suffix('"') {
+        Some(tag) => format!("{}-{}\"", tag, coding),
+        None => etag.to_string(),
+    }
+}
+
+/// Answers a request whose preconditions failed, as returned by `evaluate`.
+/// A 304 keeps the validators already set on the response and has no body.
+pub fn send_status(status: HTTPResponseStatus, response: &mut Response) {
+    if status == HTTPResponseStatus::PRECONDITIONFAILED {
+        response.body = Body::Text("412 Precondition Failed".to_string());
+        response.headers.insert("Content-Type", "text/plain");
+    }
+    response.status = status;
+    response.send();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const ETAG: &str = "\"5-abc\"";
+    const MODIFIED: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+    const EARLIER: &str = "Sat, 05 Nov 1994 08:49:37 GMT";
+
+    fn validators() -> Validators {
+        Validators {
+            etag: Some(ETAG.to_string()),
+            last_modified: http_date::parse(MODIFIED),
+        }
+    }
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        let mut head = format!("{} /file HTTP/1.1\r\n", method);
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        Request::new(&head)
+    }
+
+    fn evaluate_with(method: &str, headers: &[(&str, &str)]) -> Option<HTTPResponseStatus> {
+        evaluate(&request(method, headers), &validators())
+    }
+
+    #[test]
+    fn unconditional_requests_proceed() {
+        assert_eq!(evaluate_with("GET", &[]), None);
+        assert_eq!(evaluate_with("PUT", &[]), None);
+    }
+
+    #[test]
+    fn if_match_compares_strongly() {
+        assert_eq!(evaluate_with("PUT", &[("If-Match", ETAG)]), None);
+        assert_eq!(evaluate_with("PUT", &[("If-Match", "\"other\", *")]), None);
+        assert_eq!(
+            evaluate_with("PUT", &[("If-Match", "W/\"5-abc\"")]),
+            Some(HTTPResponseStatus::PRECONDITIONFAILED)
+        );
+        assert_eq!(
+            evaluate(
+                &request("PUT", &[("If-Match", "*")]),
+                &Validators::default()
+            ),
+            Some(HTTPResponseStatus::PRECONDITIONFAILED)
+        );
+    }
+
+    #[test]
+    fn if_match_takes_precedence_over_if_unmodified_since() {
+        let headers = [("If-Match", ETAG), ("If-Unmodified-Since", EARLIER)];
+        assert_eq!(evaluate_with("PUT", &headers), None);
+        assert_eq!(
+            evaluate_with("PUT", &[("If-Unmodified-Since", EARLIER)]),
+            Some(HTTPResponseStatus::PRECONDITIONFAILED)
+        );
+        assert_eq!(
+            evaluate_with("PUT", &[("If-Unmodified-Since", MODIFIED)]),
+            None
+        );
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        assert_eq!(
+            evaluate_with("GET", &[("If-None-Match", "W/\"5-abc\"")]),
+            Some(HTTPResponseStatus::NOTMODIFIED)
+        );
+        assert_eq!(
+            evaluate_with("GET", &[("If-None-Match", "\"5-abc-gzip\"")]),
+            Some(HTTPResponseStatus::NOTMODIFIED)
+        );
+        assert_eq!(
+            evaluate_with("GET", &[("If-None-Match", "\"other\"")]),
+            None
+        );
+    }
+
+    #[test]
+    fn if_none_match_fails_writes() {
+        assert_eq!(
+            evaluate_with("PUT", &[("If-None-Match", "*")]),
+            Some(HTTPResponseStatus::PRECONDITIONFAILED)
+        );
+        assert_eq!(
+            evaluate(
+                &request("PUT", &[("If-None-Match", "*")]),
+                &Validators::default()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let headers = [
+            ("If-None-Match", "\"other\""),
+            ("If-Modified-Since", MODIFIED),
+        ];
+        assert_eq!(evaluate_with("GET", &headers), None);
+        assert_eq!(
+            evaluate_with("GET", &[("If-Modified-Since", MODIFIED)]),
+            Some(HTTPResponseStatus::NOTMODIFIED)
+        );
+        assert_eq!(
+            evaluate_with("GET", &[("If-Modified-Since", EARLIER)]),
+            None
+        );
+    }
+
+    #[test]
+    fn if_modified_since_only_applies_to_reads() {
+        assert_eq!(
+            evaluate_with("DELETE", &[("If-Modified-Since", MODIFIED)]),
+            None
+        );
+    }
+
+    #[test]
+    fn failed_if_match_wins_over_if_none_match() {
+        let headers = [("If-Match", "\"other\""), ("If-None-Match", ETAG)];
+        assert_eq!(
+            evaluate_with("GET", &headers),
+            Some(HTTPResponseStatus::PRECONDITIONFAILED)
+        );
+    }
+
+    #[test]
+    fn ignores_unreadable_dates() {
+        assert_eq!(
+            evaluate_with("GET", &[("If-Modified-Since", "yesterday")]),
+            None
+        );
+    }
+
+    #[test]
+    fn if_range_matches_strong_tags_and_exact_dates() {
+        let validators = validators();
+        assert!(validators.matches_if_range(ETAG));
+        assert!(!validators.matches_if_range("W/\"5-abc\""));
+        assert!(!validators.matches_if_range("\"5-abc-gzip\""));
+        assert!(validators.matches_if_range(MODIFIED));
+        assert!(!validators.matches_if_range(EARLIER));
+    }
+
+    #[test]
+    fn derives_coded_tags() {
+        assert_eq!(coded_etag(ETAG, "gzip"), "\"5-abc-gzip\"");
+        assert_eq!(coded_etag("W/\"x\"", "deflate"), "W/\"x-deflate\"");
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use crate::compression::Compression;
+use crate::cors::Cors;
+use crate::reader::ReadLimits;
+use crate::request::HTTPRequestMethod;
+use crate::static_files::{Dotfiles, ServeOptions};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Connections are handled one at a time on the accepting thread.
+    Single,
+    /// Connections are dispatched to a bounded pool of worker threads.
+    Pooled,
+    /// Connections are served by tasks on the tokio runtime.
+    Async,
+}
+
+pub struct Config {
+    pub mode: Mode,
+    pub workers: usize,
+    pub queue_size: usize,
+    pub directory: Option<String>,
+    pub limits: ReadLimits,
+    pub idle_timeout: Duration,
+    pub cors: Option<Cors>,
+    /// Default compression policy for responses; `None` turns it off.
+    pub compression: Option<Compression>,
+    /// How `--directory` is served at `/files`.
+    pub files: ServeOptions,
+}
+
+impl Config {
+    pub fn from_args() -> Config {
+        let mut config = Config {
+            mode: Mode::Single,
+            workers: 4,
+            queue_size: 64,
+            directory: None,
+            limits: ReadLimits::default(),
+            idle_timeout: Duration::from_secs(5),
+            cors: None,
+            compression: Some(Compression::default()),
+            files: ServeOptions::default(),
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => {
+                    println!("warning: missing value for {}", arg);
+                    break;
+                }
+            };
+
+            match arg.as_str() {
+                "--directory" => config.directory = Some(value),
+                "--mode" => match value.as_str() {
+                    "single" => config.mode = Mode::Single,
+                    "pooled" => config.mode = Mode::Pooled,
+                    "async" => config.mode = Mode::Async,
+                    _ => println!("warning: invalid value for {}: {}", arg, value),
+                },
+                "--workers" => {
+                    config.workers = Config::parse_number(&arg, &value, config.workers).max(1)
+                }
+                "--queue-size" => {
+                    config.queue_size = Config::parse_number(&arg, &value, config.queue_size)
+                }
+                "--max-header-size" => {
+                    config.limits.max_head_size =
+                        Config::parse_number(&arg, &value, config.limits.max_head_size)
+                }
+                "--max-body-size" => {
+                    config.limits.max_body_size =
+                        Config::parse_number(&arg, &value, config.limits.max_body_size)
+                }
+                "--idle-timeout" => {
+                    let seconds =
+                        Config::parse_number(&arg, &value, config.idle_timeout.as_secs() as usize);
+                    config.idle_timeout = Duration::from_secs(seconds as u64);
+                }
+                "--cors-origin" => {
+                    config.cors().allow_origin(&value);
+                }
+                "--cors-methods" => {
+                    let methods: Vec<HTTPRequestMethod> = value
+                        .split(',')
+                        .map(|method| HTTPRequestMethod::parse(method.trim()))
+                        .filter(|method| *method != HTTPRequestMethod::UNKNOWN)
+                        .collect();
+                    config.cors().allow_methods(&methods);
+                }
+                "--cors-headers" => {
+                    let headers: Vec<&str> = value.split(',').map(|header| header.trim()).collect();
+                    config.cors().allow_headers(&headers);
+                }
+                "--cors-credentials" => {
+                    config.cors().allow_credentials(value == "true");
+                }
+                "--cors-max-age" => {
+                    let max_age = Config::parse_number(&arg, &value, 0);
+                    config.cors().max_age(max_age as u64);
+                }
+                "--compression" => match value.as_str() {
+                    "true" => config.compression = Some(Compression::default()),
+                    "false" => config.compression = None,
+                    _ => println!("warning: invalid value for {}: {}", arg, value),
+                },
+                "--compression-min-size" => {
+                    if let Some(compression) = &mut config.compression {
+                        compression.min_size =
+                            Config::parse_number(&arg, &value, compression.min_size);
+                    }
+                }
+                "--compression-level" => {
+                    if let Some(compression) = &mut config.compression {
+                        compression.level =
+                            Config::parse_number(&arg, &value, compression.level as usize).min(9)
+                                as u32;
+                    }
+                }
+                "--index" => config.files.index = value == "true",
+                "--listing" => config.files.listing = value == "true",
+                "--cache-control" => config.files.cache_control = Some(value),
+                "--dotfiles" => match value.as_str() {
+                    "allow" => config.files.dotfiles = Dotfiles::Allow,
+                    "deny" => config.files.dotfiles = Dotfiles::Deny,
+                    "ignore" => config.files.dotfiles = Dotfiles::Ignore,
+                    _ => println!("warning: invalid value for {}: {}", arg, value),
+                },
+                "--mime-sniff" => config.files.mime_types.sniff = value == "true",
+                "--mime-type" => match value.split_once('=') {
+                    Some((extension, mime_type)) => {
+                        config
+                            .files
+                            .mime_types
+                            .insert(extension.trim(), mime_type.trim());
+                    }
+                    None => println!("warning: invalid value for {}: {}", arg, value),
+                },
+                _ => println!("warning: unknown option {}", arg),
+            }
+        }
+
+        config
+    }
+
+    fn cors(&mut self) -> &mut Cors {
+        self.cors.get_or_insert_with(Cors::new)
+    }
+
+    fn parse_number(arg: &str, value: &str, default: usize) -> usize {
+        value.parse().unwrap_or_else(|_| {
+            println!("warning: invalid value for {}: {}", arg, value);
+            default
+        })
+    }
+}
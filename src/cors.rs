@@ -0,0 +1,144 @@
+use crate::request::{HTTPRequestMethod, Request};
+use crate::response::Response;
+
+/// Cross-origin policy applied by the server to every routed request.
+///
+/// An empty `allowed_methods` allows whatever the route table has registered
+/// for the path, and an empty `allowed_headers` reflects the headers a
+/// preflight asks for.
+#[derive(Clone, Default)]
+pub struct Cors {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<HTTPRequestMethod>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age: Option<u64>,
+}
+
+impl Cors {
+    pub fn new() -> Cors {
+        Cors::default()
+    }
+
+    /// Adds an origin such as `https://app.example.com`, or `*` for any origin.
+    pub fn allow_origin(&mut self, origin: &str) -> &mut Self {
+        self.allowed_origins
+            .push(origin.trim_end_matches('/').to_string());
+        self
+    }
+
+    pub fn allow_methods(&mut self, methods: &[HTTPRequestMethod]) -> &mut Self {
+        self.allowed_methods.extend_from_slice(methods);
+        self
+    }
+
+    pub fn allow_headers(&mut self, headers: &[&str]) -> &mut Self {
+        self.allowed_headers
+            .extend(headers.iter().map(|header| header.to_string()));
+        self
+    }
+
+    pub fn allow_credentials(&mut self, allow: bool) -> &mut Self {
+        self.allow_credentials = allow;
+        self
+    }
+
+    pub fn max_age(&mut self, seconds: u64) -> &mut Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn is_preflight(request: &Request) -> bool {
+        request.get_method() == HTTPRequestMethod::OPTIONS
+            && request.read_header("Origin").is_some()
+            && request
+                .read_header("Access-Control-Request-Method")
+                .is_some()
+    }
+
+    /// Adds the headers for a simple (non-preflight) cross-origin request.
+    pub fn apply(&self, request: &Request, response: &mut Response) {
+        let origin = match request.read_header("Origin") {
+            Some(origin) => origin,
+            None => return,
+        };
+        self.add_origin_headers(&origin, response);
+    }
+
+    /// Answers a preflight for a path registered under `route_methods`. A
+    /// disallowed origin or method gets a plain 204 without any
+    /// `Access-Control-*` headers, which the browser treats as a refusal.
+    pub fn preflight(
+        &self,
+        request: &Request,
+        route_methods: &[HTTPRequestMethod],
+        response: &mut Response,
+    ) {
+        let origin = request.read_header("Origin").unwrap_or_default();
+        let requested_method = request
+            .read_header("Access-Control-Request-Method")
+            .unwrap_or_default();
+        let methods = if self.allowed_methods.is_empty() {
+            route_methods
+        } else {
+            &self.allowed_methods
+        };
+        let methods: Vec<String> = methods.iter().map(|method| method.to_string()).collect();
+
+        if methods.contains(&requested_method) && self.add_origin_headers(&origin, response) {
+            response
+                .headers
+                .insert("Access-Control-Allow-Methods", methods.join(", "));
+
+            let headers = if self.allowed_headers.is_empty() {
+                request
+                    .read_header("Access-Control-Request-Headers")
+                    .unwrap_or_default()
+            } else {
+                self.allowed_headers.join(", ")
+            };
+            if !headers.is_empty() {
+                response
+                    .headers
+                    .insert("Access-Control-Allow-Headers", headers);
+            }
+
+            if let Some(max_age) = self.max_age {
+                response
+                    .headers
+                    .insert("Access-Control-Max-Age", max_age.to_string());
+            }
+        }
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    fn add_origin_headers(&self, origin: &str, response: &mut Response) -> bool {
+        if !self.allows_origin(origin) {
+            return false;
+        }
+
+        // A wildcard cannot be combined with credentials, so the origin is
+        // echoed back instead whenever credentials are allowed.
+        let any_origin = self.allowed_origins.iter().any(|allowed| allowed == "*");
+        if any_origin && !self.allow_credentials {
+            response.headers.insert("Access-Control-Allow-Origin", "*");
+        } else {
+            response
+                .headers
+                .insert("Access-Control-Allow-Origin", origin.to_string());
+            response.headers.append("Vary", "Origin");
+        }
+
+        if self.allow_credentials {
+            response
+                .headers
+                .insert("Access-Control-Allow-Credentials", "true");
+        }
+        true
+    }
+}
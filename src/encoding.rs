@@ -1,4 +1,5 @@
 
+#[allow(dead_code)]
 pub struct Encoding{
     pub precentage_encode: fn(&str) -> String,
     pub precentage_decode: fn(&str) -> String,
@@ -7,7 +8,6 @@ pub struct Encoding{
 
 
 impl Encoding {
-    #[allow(dead_code)]
     pub fn precentage_encode(input: &str) -> String {
         let mut encoded = String::new();
         for byte in input.bytes() {
@@ -17,13 +17,14 @@ impl Encoding {
                 }
                 _ => {
                     encoded.push('%');
-                    encoded.push_str(&format!("{:X}", byte));
+                    encoded.push_str(&format!("{:02X}", byte));
                 }
             }
         }
         encoded
     }
 
+    #[allow(dead_code)]
     pub  fn precentage_decode(input: &str) -> String {
         let mut decoded = String::new();
         let mut bytes = input.bytes();
@@ -0,0 +1,124 @@
+use std::str::FromStr;
+
+/// Header fields in the order they were received or added. Names keep their
+/// original spelling but are compared case-insensitively, and a name may
+/// appear more than once.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+#[allow(dead_code)]
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        HeaderMap::default()
+    }
+
+    /// Parses a `Name: value` line, splitting on the first colon only.
+    pub fn parse_line(line: &str) -> Option<(&str, &str)> {
+        let (name, value) = line.split_once(':')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        Some((name, value.trim()))
+    }
+
+    /// First value for `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// Comma-separated list values across every line for `name`, trimmed,
+    /// e.g. `Accept-Encoding` or `Connection`.
+    pub fn get_list(&self, name: &str) -> Vec<&str> {
+        self.get_all(name)
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .map(|item| item.trim())
+            .filter(|item| !item.is_empty())
+            .collect()
+    }
+
+    pub fn get_parsed<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name)?.parse().ok()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Whether the comma-separated list for `name` contains `token`, ignoring case.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_list(name)
+            .iter()
+            .any(|item| item.eq_ignore_ascii_case(token))
+    }
+
+    /// Sets `name` to a single value, replacing any existing values. The
+    /// header keeps its original position when it was already present.
+    pub fn insert<V: Into<String>>(&mut self, name: &str, value: V) {
+        let value = value.into();
+        match self
+            .entries
+            .iter()
+            .position(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            Some(position) => {
+                self.entries[position].1 = value;
+                let mut index = 0;
+                self.entries.retain(|(key, _)| {
+                    index += 1;
+                    index - 1 <= position || !key.eq_ignore_ascii_case(name)
+                });
+            }
+            None => self.entries.push((name.to_string(), value)),
+        }
+    }
+
+    /// Adds a value for `name` alongside any existing ones.
+    pub fn append<V: Into<String>>(&mut self, name: &str, value: V) {
+        self.entries.push((name.to_string(), value.into()));
+    }
+
+    /// Removes every value for `name`, returning the first.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let removed = self.get(name).map(|value| value.to_string());
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        removed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn content_length(&self) -> Option<usize> {
+        self.get_parsed("Content-Length")
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.get("Content-Type")
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use serde_json::json;
+
+use crate::encoding::Encoding;
+use crate::http_date;
+use crate::request::Request;
+use crate::response::{Body, HTTPResponseStatus, Response};
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Sends a listing of the directory at `path`, as HTML or, when the client's
+/// `Accept` prefers it, JSON. `relative` is the directory's path below the
+/// mount, and links are `prefix` followed by the path of each entry with
+/// every segment percent-encoded, so they route back through the same mount.
+/// Names starting with `.` are left out unless `show_hidden` is set.
+pub fn send(
+    request: &Request,
+    prefix: &str,
+    relative: &Path,
+    path: &Path,
+    show_hidden: bool,
+    response: &mut Response,
+) -> io::Result<()> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !show_hidden && name.starts_with('.') {
+            continue;
+        }
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    let href = |name: &str| format!("{}{}", prefix, encode_path(&relative.join(name)));
+
+    response.status = HTTPResponseStatus::OK;
+    response.headers.append("Vary", "Accept");
+    if prefers_json(request) {
+        let entries: Vec<serde_json::Value> = entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "name": entry.name,
+                    "type": if entry.is_dir { "directory" } else { "file" },
+                    "size": entry.size,
+                    "modified": entry.modified.map(http_date::format),
+                    "href": href(&entry.name),
+                })
+            })
+            .collect();
+        response.body = Body::Text(json!(entries).to_string());
+        response
+            .headers
+            .insert("Content-Type", "application/json; charset=utf-8");
+    } else {
+        let title = format!("Index of /{}", relative.to_string_lossy());
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n",
+            escape(&title)
+        );
+        if let Some(parent) = relative.parent() {
+            html.push_str(&format!(
+                "<tr><td><a href=\"{}{}\">../</a></td><td></td><td></td></tr>\n",
+                prefix,
+                encode_path(parent)
+            ));
+        }
+        for entry in &entries {
+            let name = if entry.is_dir {
+                format!("{}/", entry.name)
+            } else {
+                entry.name.clone()
+            };
+            let size = if entry.is_dir {
+                "-".to_string()
+            } else {
+                entry.size.to_string()
+            };
+            html.push_str(&format!(
+                "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                href(&entry.name),
+                escape(&name),
+                size,
+                entry.modified.map(http_date::format).unwrap_or_default()
+            ));
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        response.body = Body::Text(html);
+        response
+            .headers
+            .insert("Content-Type", "text/html; charset=utf-8");
+    }
+    response.send();
+    Ok(())
+}
+
+/// JSON is sent only when the client lists it ahead of HTML.
+fn prefers_json(request: &Request) -> bool {
+    for item in request.headers.get_list("Accept") {
+        let essence = item.split(';').next().unwrap_or("").trim();
+        if essence.eq_ignore_ascii_case("text/html") {
+            return false;
+        }
+        if essence.eq_ignore_ascii_case("application/json") {
+            return true;
+        }
+    }
+    false
+}
+
+fn encode_path(path: &Path) -> String {
+    let segments: Vec<String> = path
+        .iter()
+        .map(|segment| Encoding::precentage_encode(&segment.to_string_lossy()))
+        .collect();
+    segments.join("/")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -1,72 +1,71 @@
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use std::fs;
-use std::io::prelude::*;
-use std::net::TcpListener;
-use std::path::Path;
-
-use urlencoding;
+#![allow(clippy::upper_case_acronyms)]
 
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tokio::io::AsyncWriteExt;
+
+mod async_server;
+mod compression;
+mod conditional;
+mod config;
+mod cors;
 mod encoding;
+mod headers;
+mod http_date;
+mod listing;
+mod mime;
+mod range;
+mod reader;
 mod request;
 mod response;
 mod routes;
+mod server;
+mod static_files;
+mod thread_pool;
 
-use encoding::Encoding;
-use request::{Request, ENCODINGS};
+use conditional::Validators;
+use config::Config;
+use request::{HTTPRequestMethod, Request};
 use response::{Body, HTTPResponseStatus, Response};
 use routes::Routes;
+use server::Server;
+use static_files::{StaticError, StaticFiles};
 
 fn main() {
-    println!("Started Server on http://127.0.0.1:4221");
-
-    let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
+    let config = Config::from_args();
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                handle_connection(&mut stream);
-            }
-            Err(e) => {
-                println!("error: {}", e);
-            }
-        }
+    println!("Started Server on http://127.0.0.1:4221");
+    if let Some(directory) = &config.directory {
+        println!("Serving files from {}", directory);
     }
-}
 
-fn setup_routes() -> Routes {
-    let mut routes = Routes::new();
+    let mut server = Server::new(config);
+    setup_routes(&mut server.routes, &server.config);
+    server.run();
+}
 
+fn setup_routes(routes: &mut Routes, config: &Config) {
     routes.get("/", |_, response| {
-        response.status = HTTPResponseStatus::OK.to_string();
+        response.status = HTTPResponseStatus::OK;
         response.body = Body::Text("<h1>Hello, World!</h1>".to_string());
-        response.headers.push("Content-Type: text/html".to_string());
+        response.headers.insert("Content-Type", "text/html");
         response.send();
     });
 
     // routes.get("/:name", |request, response| {
-    //     response.status = HTTPResponseStatus::OK.to_string();
+    //     response.status = HTTPResponseStatus::OK;
     //     response.body = format!("<h1>Hello, {}!</h1>", request.params.get("name").unwrap());
-    //     response.headers.push("Content-Type: text/html".to_string());
+    //     response.headers.insert("Content-Type", "text/html");
     //     response.send();
     // });
 
     routes.get("/echo/:name", |request, response| {
-        fn send500(response: &mut Response) {
-            response.status = HTTPResponseStatus::INTERNALSERVERERROR.to_string();
-            response.body = Body::Text("500 Internal Server Error".to_string());
-            response
-                .headers
-                .push("Content-Type: text/plain".to_string());
-            response.headers.push("Content-Length: 21".to_string());
-            response.send();
-        }
-
-        let content_encoding = request
-            .read_header("Accept-Encoding")
-            .unwrap_or("".to_string());
-        let content_encoding: Vec<&str> = content_encoding.split(",").collect();
-
         let name = match request.params.get("name") {
             Some(n) => n,
             None => {
@@ -75,239 +74,288 @@ fn setup_routes() -> Routes {
             }
         };
 
-        let mut encodings: Vec<String> = Vec::new();
-        for encoding in content_encoding.iter() {
-            let encoding = encoding.trim();
-            if ENCODINGS::BASE64.to_string().to_lowercase() == *encoding {
-                encodings.push(ENCODINGS::BASE64.to_string());
-            } else if ENCODINGS::GZIP.to_string().to_lowercase() == *encoding {
-                encodings.push(ENCODINGS::GZIP.to_string());
-            } else if ENCODINGS::DEFLATE.to_string().to_lowercase() == *encoding {
-                encodings.push(ENCODINGS::DEFLATE.to_string());
-            }
-        }
-
-        if encodings.contains(&ENCODINGS::GZIP.to_string()) {
-            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-            match encoder.write_all(name.as_bytes()) {
-                Ok(_) => match encoder.finish() {
-                    Ok(compressed_bytes) => {
-                        let compressed_bytes_clone = compressed_bytes.clone(); // Clone here
-                        response.body = Body::Binary(compressed_bytes);
-                        response.headers.push("Content-Encoding: gzip".to_string());
-                        response
-                            .headers
-                            .push(format!("Content-Length: {}", compressed_bytes_clone.len()));
-                        response.status = HTTPResponseStatus::OK.to_string();
-                        response
-                            .headers
-                            .push("Content-Type: text/plain".to_string());
-                        response.send_binary();
-                    }
-                    Err(_) => {
-                        send500(response);
-                        return;
-                    }
-                },
-                Err(_) => {
-                    send500(response);
-                    return;
-                }
-            }
-        } else {
-            response.body = Body::Text(name.clone());
-            response
-                .headers
-                .push(format!("Content-Length: {}", name.len()));
-            response.status = HTTPResponseStatus::OK.to_string();
-            response
-                .headers
-                .push("Content-Type: text/plain".to_string());
-            response.send();
+        // Echoes are short, so skip the size threshold and compress whenever
+        // the client accepts it.
+        if let Some(compression) = &mut response.compression {
+            compression.min_size = 0;
         }
+        response.body = Body::Text(name.clone());
+        response.status = HTTPResponseStatus::OK;
+        response.headers.insert("Content-Type", "text/plain");
+        response.send();
     });
 
     routes.get("/user-agent", |request, response| {
-        let user_agent = request.read_header("User-Agent").unwrap();
-
-        response.status = HTTPResponseStatus::OK.to_string();
-        response.body = Body::Text(user_agent.clone());
-        response
-            .headers
-            .push("Content-Type: text/plain".to_string());
-        response
-            .headers
-            .push("Content-Length: ".to_owned() + &user_agent.len().to_string());
+        let user_agent = request.read_header("User-Agent").unwrap_or_default();
+
+        response.status = HTTPResponseStatus::OK;
+        response.body = Body::Text(user_agent);
+        response.headers.insert("Content-Type", "text/plain");
         response.send();
     });
 
-    routes.get("/files/:filename", |request, response| {
-        fn send500(response: &mut Response) {
-            response.status = HTTPResponseStatus::INTERNALSERVERERROR.to_string();
-            response.body = Body::Text("500 Internal Server Error".to_string());
-            response
-                .headers
-                .push("Content-Type: text/plain".to_string());
-            response.headers.push("Content-Length: 21".to_string());
-            response.send();
-        }
-
-        let filename = request.params.get("filename").unwrap();
-        let env_args = std::env::args().collect::<Vec<String>>();
-        let dir = env_args.get(2).cloned().unwrap_or_else(|| {
-            send500(response);
-            return "".to_string();
-        });
-        let path = format!("{}/{}", dir, Encoding::precentage_decode(filename));
-
-        if !fs::metadata(&path).is_ok() {
-            response.status = HTTPResponseStatus::NOTFOUND.to_string();
-            response.body = Body::Text("404 Not Found".to_string());
-            response
-                .headers
-                .push("Content-Type: text/plain".to_string());
-            response.headers.push("Content-Length: 13".to_string());
-            response.send();
-            return;
-        }
-
-        if fs::metadata(&path).unwrap().is_dir() {
-            response.status = HTTPResponseStatus::FORBIDDEN.to_string();
-            response.body = Body::Text("403 Forbidden".to_string());
-            response
-                .headers
-                .push("Content-Type: text/plain".to_string());
-            response.headers.push("Content-Length: 13".to_string());
-            response.send();
-            return;
-        }
-
-        let contents = match fs::read_to_string(&path) {
-            Ok(contents) => contents,
-            Err(_) => {
-                send500(response);
-                return;
-            }
-        };
-
-        response.status = HTTPResponseStatus::OK.to_string();
-
-        let contents = contents.replace("\u{0}", "");
+    if let Some(directory) = &config.directory {
+        routes.serve_dir("/files", directory, config.files.clone());
+        // Writes go through the same root and dotfile policy as reads, and
+        // take the rest of the path so nested directories can be written to.
+        let mut files = StaticFiles::new(directory);
+        files.options = config.files.clone();
+        setup_file_writes(routes, files);
+    }
+}
 
-        response.body = Body::Text(contents.clone());
-        response
-            .headers
-            .push("Content-Type: application/octet-stream".to_string());
-        response
-            .headers
-            .push("Content-Length: ".to_owned() + &contents.len().to_string());
-        response.send();
+fn setup_file_writes(routes: &mut Routes, files: StaticFiles) {
+    let post_files = files.clone();
+    routes.post_async("/files/*path", move |request, response| {
+        write_file(post_files.clone(), WriteMode::Create, request, response)
     });
 
-    routes.post("/files/:filename", |request, response| {
-        fn send500(response: &mut Response) {
-            response.status = "500 Internal Server Error".to_string();
-            response.body = Body::Text("500 Internal Server Error".to_string());
-            response
-                .headers
-                .push("Content-Type: text/plain".to_string());
-            response.headers.push("Content-Length: 21".to_string());
-            response.send();
-        }
-
-        let filename = match request.params.get("filename") {
-            Some(f) => f,
+    let put_files = files.clone();
+    routes.route_async(
+        HTTPRequestMethod::PUT,
+        "/files/*path",
+        move |request, response| {
+            write_file(put_files.clone(), WriteMode::Replace, request, response)
+        },
+    );
+
+    let patch_files = files.clone();
+    routes.route_async(
+        HTTPRequestMethod::PATCH,
+        "/files/*path",
+        move |request, response| {
+            write_file(patch_files.clone(), WriteMode::Append, request, response)
+        },
+    );
+
+    routes.delete("/files/*path", move |request, response| {
+        let relative = match request.params.get("path") {
+            Some(relative) => relative,
             None => {
                 send500(response);
                 return;
             }
         };
-
-        let env_args: Vec<String> = std::env::args().collect();
-        let dir = match env_args.get(2) {
-            Some(d) => d.clone(),
-            None => {
-                send500(response);
+        // A symlink is removed itself, never the file it points to.
+        let path = match files.resolve_entry(relative) {
+            Ok(path) => path,
+            Err(error) => {
+                static_files::send_error(error, response);
                 return;
             }
         };
-
-        let decoded_filename = match urlencoding::decode(filename) {
-            Ok(df) => df.to_string(),
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => {
+                static_files::send_error(StaticError::Forbidden, response);
+                return;
+            }
+            Ok(metadata) => metadata,
             Err(_) => {
-                send500(response);
+                static_files::send_error(StaticError::NotFound, response);
                 return;
             }
         };
 
-        let path = format!("{}/{}", dir, decoded_filename);
-
-        if !Path::new(&dir).exists() {
-            response.status = "404 Not Found".to_string();
-            response.body = Body::Text("404 Not Found".to_string());
-            response
-                .headers
-                .push("Content-Type: text/plain".to_string());
-            response.headers.push("Content-Length: 13".to_string());
-            response.send();
+        // Preconditions are checked against what a GET would have served,
+        // which for a symlink is its target.
+        let metadata = fs::metadata(&path).unwrap_or(metadata);
+        let validators = Validators::from_metadata(&metadata);
+        if let Some(status) = conditional::evaluate(&request, &validators) {
+            conditional::send_status(status, response);
             return;
         }
 
-        let body = request.body.clone();
-        let body = body.replace("\u{0}", "");
-
-        let content_encoding = request
-            .read_header("Accept-Encoding")
-            .unwrap_or("".to_string());
-
-        match fs::write(&path, &body) {
+        match fs::remove_file(&path) {
             Ok(_) => {
-                println!("File written to {}", path);
-                response.status = "201 Created".to_string();
-                response.body = Body::Text("201 Created".to_string());
-                response
-                    .headers
-                    .push("Content-Type: text/plain".to_string());
-                response.headers.push("Content-Length: 11".to_string());
-                response
-                    .headers
-                    .push("Content-Encoding: ".to_owned() + &content_encoding);
+                println!("File removed from {}", path.display());
+                response.status = HTTPResponseStatus::NOCONTENT;
                 response.send();
             }
-            Err(_) => {
-                send500(response);
-                return;
-            }
+            Err(_) => send500(response),
         }
     });
+}
 
-    routes
+fn send500(response: &mut Response) {
+    response.status = HTTPResponseStatus::INTERNALSERVERERROR;
+    response.body = Body::Text("500 Internal Server Error".to_string());
+    response.headers.insert("Content-Type", "text/plain");
+    response.send();
 }
 
-pub fn handle_connection(stream: &mut std::net::TcpStream) {
-    let mut buffer = [0; 512];
-    stream.read(&mut buffer).unwrap();
-    let request = Request::new(&String::from_utf8_lossy(&buffer[..]));
+fn send409(response: &mut Response) {
+    response.status = HTTPResponseStatus::CONFLICT;
+    response.body = Body::Text("409 Conflict".to_string());
+    response.headers.insert("Content-Type", "text/plain");
+    response.send();
+}
 
-    let mut routes = setup_routes();
+#[derive(Clone, Copy, PartialEq)]
+enum WriteMode {
+    /// POST: creates or overwrites the file, answering 201 either way.
+    Create,
+    /// PUT: creates (201) or replaces (204) the file.
+    Replace,
+    /// PATCH: appends the body, creating the file if needed (201, else 204).
+    Append,
+}
 
-    match routes.resolve(request.get_method(), &request.path) {
-        Some(resolved) => {
-            let (handler, params) = resolved;
-            let mut response = Response::new(stream);
-            let request_with_params = request.with_params(params);
-            handler(request_with_params, &mut response);
-        }
+/// Shared by POST, PUT and PATCH on `/files/*path`. Parent directories are
+/// created as needed. `If-None-Match: *` refuses to touch an existing file
+/// with 409, including one created by a concurrent request while this one was
+/// writing, and other preconditions such as `If-Match` guard against
+/// overwriting a version of the file the client has not seen.
+async fn write_file(
+    files: StaticFiles,
+    mode: WriteMode,
+    request: Request,
+    mut response: Response,
+) -> Response {
+    let relative = match request.params.get("path") {
+        Some(relative) => relative,
         None => {
-            let mut response = Response::new(stream);
-            response.status = HTTPResponseStatus::NOTFOUND.to_string();
-            response.body = Body::Text("404 Not Found".to_string());
-            response
-                .headers
-                .push("Content-Type: text/plain".to_string());
-            response.headers.push("Content-Length: 13".to_string());
-            response.send();
-            response.send();
+            send500(&mut response);
+            return response;
+        }
+    };
+    let path = match files.resolve_for_write(relative) {
+        Ok(path) => path,
+        Err(error) => {
+            static_files::send_error(error, &mut response);
+            return response;
+        }
+    };
+
+    let existing = tokio::fs::metadata(&path).await.ok();
+    if existing.as_ref().is_some_and(|metadata| metadata.is_dir()) {
+        static_files::send_error(StaticError::Forbidden, &mut response);
+        return response;
+    }
+    let create_only = request.headers.has_token("If-None-Match", "*");
+    if existing.is_some() && create_only {
+        send409(&mut response);
+        return response;
+    }
+    let validators = match &existing {
+        Some(metadata) => Validators::from_metadata(metadata),
+        None => Validators::default(),
+    };
+    if let Some(status) = conditional::evaluate(&request, &validators) {
+        conditional::send_status(status, &mut response);
+        return response;
+    }
+
+    let written = match mode {
+        WriteMode::Append => append_file(path.clone(), request.body.clone(), create_only).await,
+        WriteMode::Create | WriteMode::Replace => {
+            replace_file(&path, &request.body, create_only).await
         }
+    };
+    match written {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            send409(&mut response);
+            return response;
+        }
+        Err(e) => {
+            println!("error: {}", e);
+            send500(&mut response);
+            return response;
+        }
+    }
+    println!("File written to {}", path.display());
+
+    if let Ok(metadata) = tokio::fs::metadata(&path).await {
+        Validators::from_metadata(&metadata).apply(&mut response);
+    }
+    if existing.is_some() && mode != WriteMode::Create {
+        response.status = HTTPResponseStatus::NOCONTENT;
+    } else {
+        response.status = HTTPResponseStatus::CREATED;
+        response.body = Body::Text("201 Created".to_string());
+        response.headers.insert("Content-Type", "text/plain");
+    }
+    response.send();
+    response
+}
+
+/// Writes `contents` to a temporary file beside `path` and renames it into
+/// place, so readers see either the old file or the complete new one. With
+/// `create_new` the file is hard-linked into place instead, which fails with
+/// `AlreadyExists` rather than replacing a file that appeared meanwhile.
+async fn replace_file(path: &Path, contents: &[u8], create_new: bool) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
     }
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    let temporary = path.with_file_name(format!(".{}.{}-{}.tmp", name, std::process::id(), nanos));
+
+    let written = async {
+        let mut file = tokio::fs::File::create(&temporary).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        if create_new {
+            tokio::fs::hard_link(&temporary, path).await
+        } else {
+            tokio::fs::rename(&temporary, path).await
+        }
+    }
+    .await;
+    // After a hard link the file has a second name to drop.
+    if written.is_err() || create_new {
+        let _ = tokio::fs::remove_file(&temporary).await;
+    }
+    written
+}
+
+/// Appends in progress, by path. Holding a path's lock while appending keeps
+/// concurrent appends to one file from interleaving.
+static APPENDING: LazyLock<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+/// Appends `contents` to the file at `path` with a single append-mode write,
+/// creating it if needed. With `create_new` an existing file is an
+/// `AlreadyExists` error instead. Appends to the same path from this server
+/// are applied one at a time and never interleave.
+async fn append_file(path: PathBuf, contents: Bytes, create_new: bool) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::task::spawn_blocking(move || {
+        let lock = APPENDING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(path.clone())
+            .or_default()
+            .clone();
+
+        let written = {
+            let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+            fs::OpenOptions::new()
+                .create(true)
+                .create_new(create_new)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| {
+                    file.write_all(&contents)?;
+                    file.sync_all()
+                })
+        };
+
+        // The last one out drops the path's lock, so the map stays small.
+        let mut appending = APPENDING.lock().unwrap_or_else(PoisonError::into_inner);
+        if Arc::strong_count(&lock) == 2 {
+            appending.remove(&path);
+        }
+        written
+    })
+    .await
+    .map_err(io::Error::other)?
 }
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub const DEFAULT: &str = "application/octet-stream";
+
+/// How many leading bytes of a file `sniff` looks at.
+pub const SNIFF_LENGTH: usize = 512;
+
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+/// Maps file names to media types: the built-in extension table, overridden
+/// per extension by `insert`. Files with an unknown extension are served as
+/// `application/octet-stream` unless `sniff` is on.
+#[derive(Clone, Debug, Default)]
+pub struct MimeTypes {
+    overrides: HashMap<String, String>,
+    pub sniff: bool,
+}
+
+impl MimeTypes {
+    #[allow(dead_code)]
+    pub fn new() -> MimeTypes {
+        MimeTypes::default()
+    }
+
+    /// Serves files ending in `.extension` as `mime_type`.
+    pub fn insert(&mut self, extension: &str, mime_type: &str) -> &mut Self {
+        self.overrides.insert(
+            extension.trim_start_matches('.').to_lowercase(),
+            mime_type.to_string(),
+        );
+        self
+    }
+
+    /// The media type for `path` by extension alone.
+    pub fn lookup(&self, path: &Path) -> Option<String> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        if let Some(mime_type) = self.overrides.get(&extension) {
+            return Some(mime_type.clone());
+        }
+        EXTENSIONS
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, mime_type)| mime_type.to_string())
+    }
+
+    /// The `Content-Type` for the file at `path`. When the extension is
+    /// unknown and sniffing is on, the file's first bytes decide and the file
+    /// is rewound afterwards. Text types are declared as UTF-8.
+    pub fn content_type<R: Read + Seek>(&self, path: &Path, file: &mut R) -> io::Result<String> {
+        let mime_type = match self.lookup(path) {
+            Some(mime_type) => mime_type,
+            None if !self.sniff => DEFAULT.to_string(),
+            None => {
+                let mut head = Vec::with_capacity(SNIFF_LENGTH);
+                file.take(SNIFF_LENGTH as u64).read_to_end(&mut head)?;
+                file.seek(SeekFrom::Start(0))?;
+                sniff(&head).to_string()
+            }
+        };
+        Ok(with_charset(&mime_type))
+    }
+}
+
+/// Adds `charset=utf-8` to textual types that do not already name a charset.
+pub fn with_charset(mime_type: &str) -> String {
+    let essence = mime_type.split(';').next().unwrap_or("").trim();
+    let textual = essence.starts_with("text/")
+        || matches!(essence, "application/json" | "application/javascript");
+    if textual && !mime_type.to_lowercase().contains("charset=") {
+        format!("{}; charset=utf-8", mime_type)
+    } else {
+        mime_type.to_string()
+    }
+}
+
+/// Guesses a media type from the leading bytes of a file by its magic number,
+/// or as plain text when it decodes as UTF-8 without control characters.
+pub fn sniff(head: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\0asm", "application/wasm"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"ID3", "audio/mpeg"),
+        (b"OggS", "audio/ogg"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+    ];
+    if let Some((_, mime_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+    {
+        return mime_type;
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" {
+        match &head[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            _ => {}
+        }
+    }
+
+    // A multi-byte character may be cut off at the end of the sample.
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or("")
+        }
+        Err(_) => return DEFAULT,
+    };
+    if text
+        .chars()
+        .any(|c| c.is_control() && !c.is_ascii_whitespace())
+    {
+        return DEFAULT;
+    }
+
+    let start = text.trim_start().to_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html"
+    } else if start.starts_with("<?xml") {
+        "application/xml"
+    } else if start.starts_with("<svg") {
+        "image/svg+xml"
+    } else if head.is_empty() {
+        DEFAULT
+    } else {
+        "text/plain"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn lookup(mime_types: &MimeTypes, name: &str) -> Option<String> {
+        mime_types.lookup(Path::new(name))
+    }
+
+    #[test]
+    fn looks_up_known_extensions() {
+        let mime_types = MimeTypes::new();
+        assert_eq!(
+            lookup(&mime_types, "index.html"),
+            Some("text/html".to_string())
+        );
+        assert_eq!(
+            lookup(&mime_types, "photo.JPG"),
+            Some("image/jpeg".to_string())
+        );
+        assert_eq!(
+            lookup(&mime_types, "archive.tar.gz"),
+            Some("application/gzip".to_string())
+        );
+        assert_eq!(lookup(&mime_types, "unknown.xyz"), None);
+        assert_eq!(lookup(&mime_types, "README"), None);
+        assert_eq!(lookup(&mime_types, ".html"), None);
+    }
+
+    #[test]
+    fn overrides_take_precedence() {
+        let mut mime_types = MimeTypes::new();
+        mime_types
+            .insert(".JS", "application/javascript")
+            .insert("xyz", "chemical/x-xyz");
+        assert_eq!(
+            lookup(&mime_types, "app.js"),
+            Some("application/javascript".to_string())
+        );
+        assert_eq!(
+            lookup(&mime_types, "molecule.XYZ"),
+            Some("chemical/x-xyz".to_string())
+        );
+        assert_eq!(
+            lookup(&mime_types, "style.css"),
+            Some("text/css".to_string())
+        );
+    }
+
+    #[test]
+    fn adds_charset_to_textual_types() {
+        assert_eq!(with_charset("text/plain"), "text/plain; charset=utf-8");
+        assert_eq!(
+            with_charset("application/json"),
+            "application/json; charset=utf-8"
+        );
+        assert_eq!(
+            with_charset("text/html; charset=ISO-8859-1"),
+            "text/html; charset=ISO-8859-1"
+        );
+        assert_eq!(
+            with_charset("text/csv; Charset=utf-16"),
+            "text/csv; Charset=utf-16"
+        );
+        assert_eq!(with_charset("image/png"), "image/png");
+        assert_eq!(with_charset("application/xml"), "application/xml");
+    }
+
+    #[test]
+    fn sniffs_magic_numbers() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0\0\x10JFIF"), "image/jpeg");
+        assert_eq!(sniff(b"GIF89a\x01\0"), "image/gif");
+        assert_eq!(sniff(b"%PDF-1.7\n"), "application/pdf");
+        assert_eq!(sniff(b"\x1f\x8b\x08\0"), "application/gzip");
+    }
+
+    #[test]
+    fn sniffs_riff_subtypes() {
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WAVEfmt "), "audio/wav");
+        assert_eq!(sniff(b"RIFF\x24\0\0\0AVI LIST"), DEFAULT);
+        // Too short to hold a subtype, and plain ASCII otherwise.
+        assert_eq!(sniff(b"RIFF"), "text/plain");
+    }
+
+    #[test]
+    fn sniffs_markup() {
+        assert_eq!(sniff(b"  \n<!DOCTYPE html><html>"), "text/html");
+        assert_eq!(sniff(b"<HTML><body>"), "text/html");
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?>"), "application/xml");
+        assert_eq!(
+            sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\">"),
+            "image/svg+xml"
+        );
+    }
+
+    #[test]
+    fn sniffs_text() {
+        assert_eq!(sniff(b"hello\tworld\r\n"), "text/plain");
+        assert_eq!(sniff("naïve café".as_bytes()), "text/plain");
+        assert_eq!(sniff(b""), DEFAULT);
+        assert_eq!(sniff(b"hello\0world"), DEFAULT);
+        assert_eq!(sniff(b"\x1b[31mred"), DEFAULT);
+    }
+
+    #[test]
+    fn tolerates_utf8_cut_at_the_end_of_the_sample() {
+        let text = "café".as_bytes();
+        assert_eq!(sniff(&text[..text.len() - 1]), "text/plain");
+        // Invalid bytes before the end are not text.
+        assert_eq!(sniff(b"caf\xc3 au lait"), DEFAULT);
+        assert_eq!(sniff(b"\xff\xfe\xfd"), DEFAULT);
+    }
+
+    #[test]
+    fn sniffs_only_when_enabled_and_rewinds() {
+        let mut mime_types = MimeTypes::new();
+        let mut file = Cursor::new(b"<!doctype html><p>hi</p>".to_vec());
+        assert_eq!(
+            mime_types
+                .content_type(Path::new("page"), &mut file)
+                .unwrap(),
+            DEFAULT
+        );
+
+        mime_types.sniff = true;
+        assert_eq!(
+            mime_types
+                .content_type(Path::new("page"), &mut file)
+                .unwrap(),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(file.position(), 0);
+
+        // A known extension wins without reading the file.
+        let mut file = Cursor::new(b"\x89PNG\r\n\x1a\n".to_vec());
+        assert_eq!(
+            mime_types
+                .content_type(Path::new("notes.txt"), &mut file)
+                .unwrap(),
+            "text/plain; charset=utf-8"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+/// Most ranges honoured in one request. Anything beyond this is more likely
+/// an attempt to amplify the response than a real client.
+const MAX_RANGES: usize = 16;
+
+/// An inclusive span of bytes within a representation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// The `Content-Range` value for this span of a `total`-byte body.
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+    Satisfiable(Vec<ByteRange>),
+    /// None of the requested ranges overlap the body.
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header such as `bytes=0-499, 1000-, -200` against a body
+/// of `length` bytes. Returns `None` when the header should be ignored and
+/// the whole body sent: a unit other than `bytes`, a syntax error, or too
+/// many ranges. Overlapping and adjacent ranges are merged.
+pub fn parse(header: &str, length: u64) -> Option<Ranges> {
+    let (unit, specs) = header.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(|spec| spec.trim())
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (first, last) = spec.split_once('-')?;
+        let range = match (first.trim(), last.trim()) {
+            ("", suffix) => {
+                let suffix = parse_position(suffix)?;
+                if suffix == 0 || length == 0 {
+                    continue;
+                }
+                ByteRange {
+                    start: length.saturating_sub(suffix),
+                    end: length - 1,
+                }
+            }
+            (first, last) => {
+                let start = parse_position(first)?;
+                let end = match last {
+                    "" => u64::MAX,
+                    last => parse_position(last)?,
+                };
+                if end < start {
+                    return None;
+                }
+                if start >= length {
+                    continue;
+                }
+                ByteRange {
+                    start,
+                    end: end.min(length - 1),
+                }
+            }
+        };
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() {
+        return Some(Ranges::Unsatisfiable);
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end)
+            }
+            _ => merged.push(range),
+        }
+    }
+    Some(Ranges::Satisfiable(merged))
+}
+
+fn parse_position(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn ranges(spans: &[(u64, u64)]) -> Option<Ranges> {
+        Some(Ranges::Satisfiable(
+            spans
+                .iter()
+                .map(|&(start, end)| ByteRange { start, end })
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn parses_closed_and_open_ranges() {
+        assert_eq!(parse("bytes=0-499", 1000), ranges(&[(0, 499)]));
+        assert_eq!(parse("bytes=500-", 1000), ranges(&[(500, 999)]));
+        assert_eq!(parse("BYTES = 0-0", 1000), ranges(&[(0, 0)]));
+    }
+
+    #[test]
+    fn clamps_ends_past_the_body() {
+        assert_eq!(parse("bytes=900-5000", 1000), ranges(&[(900, 999)]));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse("bytes=-200", 1000), ranges(&[(800, 999)]));
+        assert_eq!(parse("bytes=-5000", 1000), ranges(&[(0, 999)]));
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(
+            parse("bytes=500-599, 0-99, 50-149, 150-199", 1000),
+            ranges(&[(0, 199), (500, 599)])
+        );
+        assert_eq!(parse("bytes=0-, -100", 1000), ranges(&[(0, 999)]));
+    }
+
+    #[test]
+    fn skips_unsatisfiable_ranges_among_others() {
+        assert_eq!(parse("bytes=2000-3000, 0-9", 1000), ranges(&[(0, 9)]));
+    }
+
+    #[test]
+    fn reports_unsatisfiable() {
+        assert_eq!(parse("bytes=1000-", 1000), Some(Ranges::Unsatisfiable));
+        assert_eq!(parse("bytes=-0", 1000), Some(Ranges::Unsatisfiable));
+        assert_eq!(parse("bytes=0-", 0), Some(Ranges::Unsatisfiable));
+        assert_eq!(parse("bytes=-10", 0), Some(Ranges::Unsatisfiable));
+    }
+
+    #[test]
+    fn ignores_invalid_headers() {
+        for header in [
+            "items=0-9",
+            "bytes",
+            "bytes=",
+            "bytes=9-0",
+            "bytes=a-b",
+            "bytes=+1-2",
+            "bytes=0-9;1",
+            "bytes=-",
+        ] {
+            assert_eq!(parse(header, 1000), None, "{}", header);
+        }
+    }
+
+    #[test]
+    fn ignores_too_many_ranges() {
+        let header = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse(&header, 1000), None);
+    }
+
+    #[test]
+    fn formats_content_range() {
+        let range = ByteRange { start: 0, end: 99 };
+        assert_eq!(range.len(), 100);
+        assert_eq!(range.content_range(1000), "bytes 0-99/1000");
+    }
+}
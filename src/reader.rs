@@ -0,0 +1,487 @@
+use std::io::{self, Read};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::headers::HeaderMap;
+use crate::request::Request;
+
+const READ_CHUNK_SIZE: usize = 8192;
+/// Longest chunk-size line accepted, extensions included.
+const MAX_CHUNK_LINE: usize = 4096;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReadLimits {
+    pub max_head_size: usize,
+    pub max_body_size: usize,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        ReadLimits {
+            max_head_size: 8 * 1024,
+            max_body_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    /// The peer closed the connection before sending anything.
+    Closed,
+    Io(io::Error),
+    Malformed,
+    HeadTooLarge,
+    BodyTooLarge,
+    /// A transfer coding other than `chunked` was applied to the body.
+    UnsupportedTransferEncoding,
+}
+
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> Self {
+        ReadError::Io(error)
+    }
+}
+
+/// Reads requests off a stream, buffering until the head terminator and then
+/// either exactly `Content-Length` bytes of body or a chunked body through its
+/// last chunk and trailers. Bytes read past the end of one request are kept
+/// for the next call.
+pub struct RequestReader {
+    buffer: Vec<u8>,
+    limits: ReadLimits,
+    pending: Option<(Request, Framing)>,
+}
+
+enum Framing {
+    Length(usize),
+    Chunked(ChunkedDecoder),
+}
+
+impl RequestReader {
+    pub fn new(limits: ReadLimits) -> RequestReader {
+        RequestReader {
+            buffer: Vec::new(),
+            limits,
+            pending: None,
+        }
+    }
+
+    pub fn read_request<R: Read>(&mut self, stream: &mut R) -> Result<Request, ReadError> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some(request) = self.parse()? {
+                return Ok(request);
+            }
+
+            let read = match stream.read(&mut chunk) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ReadError::Io(e)),
+            };
+
+            if read == 0 {
+                if self.buffer.is_empty() && self.pending.is_none() {
+                    return Err(ReadError::Closed);
+                }
+                return Err(ReadError::Malformed);
+            }
+
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    pub async fn read_request_async<R>(&mut self, stream: &mut R) -> Result<Request, ReadError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some(request) = self.parse()? {
+                return Ok(request);
+            }
+
+            let read = match stream.read(&mut chunk).await {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ReadError::Io(e)),
+            };
+
+            if read == 0 {
+                if self.buffer.is_empty() && self.pending.is_none() {
+                    return Err(ReadError::Closed);
+                }
+                return Err(ReadError::Malformed);
+            }
+
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    fn parse(&mut self) -> Result<Option<Request>, ReadError> {
+        if self.pending.is_none() {
+            let head_end = match find_head_end(&self.buffer) {
+                Some(end) => end,
+                None if self.buffer.len() > self.limits.max_head_size => {
+                    return Err(ReadError::HeadTooLarge)
+                }
+                None => return Ok(None),
+            };
+            if head_end > self.limits.max_head_size {
+                return Err(ReadError::HeadTooLarge);
+            }
+
+            let head =
+                std::str::from_utf8(&self.buffer[..head_end]).map_err(|_| ReadError::Malformed)?;
+            if !is_valid_request_line(head.lines().next().unwrap_or("")) {
+                return Err(ReadError::Malformed);
+            }
+
+            let request = Request::new(head);
+            let framing = body_framing(&request, &self.limits)?;
+
+            self.buffer.drain(..head_end);
+            self.pending = Some((request, framing));
+        }
+
+        let body = match &mut self.pending {
+            Some((_, Framing::Length(content_length))) => {
+                if self.buffer.len() < *content_length {
+                    return Ok(None);
+                }
+                let rest = self.buffer.split_off(*content_length);
+                std::mem::replace(&mut self.buffer, rest)
+            }
+            Some((_, Framing::Chunked(decoder))) => {
+                if !decoder.decode(&mut self.buffer, &self.limits)? {
+                    return Ok(None);
+                }
+                std::mem::take(&mut decoder.body)
+            }
+            None => return Ok(None),
+        };
+
+        let (mut request, framing) = self.pending.take().unwrap();
+        if let Framing::Chunked(decoder) = framing {
+            // The body is now plain bytes, so describe it the way a
+            // `Content-Length` request would be.
+            request.headers.remove("Transfer-Encoding");
+            request
+                .headers
+                .insert("Content-Length", body.len().to_string());
+            request.trailers = decoder.trailers;
+        }
+        request.body = Bytes::from(body);
+
+        Ok(Some(request))
+    }
+}
+
+/// Works out how the body of `request` is delimited. A `Transfer-Encoding`
+/// must end in `chunked`, and is refused alongside a `Content-Length` since
+/// the two disagreeing is a classic request smuggling vector. For the same
+/// reason `Content-Length` must be all digits, and repeats must agree.
+fn body_framing(request: &Request, limits: &ReadLimits) -> Result<Framing, ReadError> {
+    let codings = request.headers.get_list("Transfer-Encoding");
+    if let Some(last) = codings.last() {
+        if !last.eq_ignore_ascii_case("chunked") || request.headers.contains("Content-Length") {
+            return Err(ReadError::Malformed);
+        }
+        if codings.len() > 1 {
+            return Err(ReadError::UnsupportedTransferEncoding);
+        }
+        return Ok(Framing::Chunked(ChunkedDecoder::new()));
+    }
+
+    // Every value must be plain digits (no sign), and repeated fields must
+    // agree, or two parsers could disagree on where the body ends.
+    let lengths = request.headers.get_list("Content-Length");
+    if request.headers.contains("Content-Length") && lengths.is_empty() {
+        return Err(ReadError::Malformed);
+    }
+    let mut content_length = 0;
+    for (index, length) in lengths.iter().enumerate() {
+        if !length.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(ReadError::Malformed);
+        }
+        let length = length.parse::<usize>().map_err(|_| ReadError::Malformed)?;
+        if index > 0 && length != content_length {
+            return Err(ReadError::Malformed);
+        }
+        content_length = length;
+    }
+    if content_length > limits.max_body_size {
+        return Err(ReadError::BodyTooLarge);
+    }
+    Ok(Framing::Length(content_length))
+}
+
+enum ChunkState {
+    Size,
+    /// Bytes left in the current chunk.
+    Data(usize),
+    Trailers,
+}
+
+/// Incremental decoder for a chunked body. Chunk extensions are skipped, and
+/// trailers are counted against the head size limit.
+struct ChunkedDecoder {
+    state: ChunkState,
+    body: Vec<u8>,
+    trailers: HeaderMap,
+    trailers_size: usize,
+}
+
+impl ChunkedDecoder {
+    fn new() -> ChunkedDecoder {
+        ChunkedDecoder {
+            state: ChunkState::Size,
+            body: Vec::new(),
+            trailers: HeaderMap::new(),
+            trailers_size: 0,
+        }
+    }
+
+    /// Consumes as much of `buffer` as possible, returning `true` once the
+    /// last chunk and trailers have been read.
+    fn decode(&mut self, buffer: &mut Vec<u8>, limits: &ReadLimits) -> Result<bool, ReadError> {
+        loop {
+            match self.state {
+                ChunkState::Size => {
+                    let line = match take_line(buffer)? {
+                        Some(line) => line,
+                        None if buffer.len() > MAX_CHUNK_LINE => return Err(ReadError::Malformed),
+                        None => return Ok(false),
+                    };
+                    let size = line.split(';').next().unwrap_or("").trim();
+                    if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                        return Err(ReadError::Malformed);
+                    }
+                    let size =
+                        usize::from_str_radix(size, 16).map_err(|_| ReadError::BodyTooLarge)?;
+                    if size > limits.max_body_size.saturating_sub(self.body.len()) {
+                        return Err(ReadError::BodyTooLarge);
+                    }
+                    self.state = if size == 0 {
+                        ChunkState::Trailers
+                    } else {
+                        ChunkState::Data(size)
+                    };
+                }
+                ChunkState::Data(size) => {
+                    if buffer.len() < size + 2 {
+                        return Ok(false);
+                    }
+                    if &buffer[size..size + 2] != b"\r\n" {
+                        return Err(ReadError::Malformed);
+                    }
+                    self.body.extend_from_slice(&buffer[..size]);
+                    buffer.drain(..size + 2);
+                    self.state = ChunkState::Size;
+                }
+                ChunkState::Trailers => {
+                    let remaining = limits.max_head_size.saturating_sub(self.trailers_size);
+                    let line = match take_line(buffer)? {
+                        Some(line) => line,
+                        None if buffer.len() > remaining => return Err(ReadError::HeadTooLarge),
+                        None => return Ok(false),
+                    };
+                    if line.is_empty() {
+                        return Ok(true);
+                    }
+                    self.trailers_size += line.len() + 2;
+                    if self.trailers_size > limits.max_head_size {
+                        return Err(ReadError::HeadTooLarge);
+                    }
+                    match HeaderMap::parse_line(&line) {
+                        Some((name, value)) => self.trailers.append(name, value),
+                        None => return Err(ReadError::Malformed),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Removes one CRLF-terminated line from the front of `buffer`, if a whole
+/// line has arrived.
+fn take_line(buffer: &mut Vec<u8>) -> Result<Option<String>, ReadError> {
+    let end = match buffer.windows(2).position(|window| window == b"\r\n") {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    let line = String::from_utf8(buffer[..end].to_vec()).map_err(|_| ReadError::Malformed)?;
+    buffer.drain(..end + 2);
+    Ok(Some(line))
+}
+
+fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    buffer
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|position| position + 4)
+}
+
+fn is_valid_request_line(line: &str) -> bool {
+    let parts: Vec<&str> = line.split(' ').collect();
+    parts.len() == 3 && !parts[0].is_empty() && !parts[1].is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Hands out one byte per read, so every line and chunk arrives split.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn read(raw: &[u8]) -> Result<Request, ReadError> {
+        RequestReader::new(ReadLimits::default()).read_request(&mut &raw[..])
+    }
+
+    fn chunked(body: &str) -> Vec<u8> {
+        format!(
+            "POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}",
+            body
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn reads_content_length_body() {
+        let request = read(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+        assert_eq!(&request.body[..], b"hello");
+    }
+
+    #[test]
+    fn keeps_pipelined_bytes_for_the_next_request() {
+        let raw = b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n";
+        let mut stream = &raw[..];
+        let mut reader = RequestReader::new(ReadLimits::default());
+        assert_eq!(reader.read_request(&mut stream).unwrap().path, "/a");
+        assert_eq!(reader.read_request(&mut stream).unwrap().path, "/b");
+        assert!(matches!(
+            reader.read_request(&mut stream),
+            Err(ReadError::Closed)
+        ));
+    }
+
+    #[test]
+    fn rejects_signed_content_length() {
+        let result = read(b"POST / HTTP/1.1\r\nContent-Length: +0\r\n\r\n");
+        assert!(matches!(result, Err(ReadError::Malformed)));
+    }
+
+    #[test]
+    fn rejects_conflicting_content_lengths() {
+        let result = read(b"POST / HTTP/1.1\r\nContent-Length: 0\r\nContent-Length: 3\r\n\r\nabc");
+        assert!(matches!(result, Err(ReadError::Malformed)));
+        let result = read(b"POST / HTTP/1.1\r\nContent-Length: 3, 4\r\n\r\nabcd");
+        assert!(matches!(result, Err(ReadError::Malformed)));
+    }
+
+    #[test]
+    fn accepts_repeated_equal_content_lengths() {
+        let request = read(b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\nabc");
+        assert_eq!(&request.unwrap().body[..], b"abc");
+    }
+
+    #[test]
+    fn decodes_chunked_body() {
+        let request = read(&chunked(
+            "5\r\nhello\r\n6;name=value\r\n world\r\n0\r\n\r\n",
+        ))
+        .unwrap();
+        assert_eq!(&request.body[..], b"hello world");
+        assert_eq!(request.headers.get("Content-Length"), Some("11"));
+        assert!(!request.headers.contains("Transfer-Encoding"));
+    }
+
+    #[test]
+    fn decodes_chunked_body_split_across_reads() {
+        let raw = chunked("a\r\n0123456789\r\n3\r\nabc\r\n0\r\nChecksum: 42\r\n\r\n");
+        let mut reader = RequestReader::new(ReadLimits::default());
+        let request = reader.read_request(&mut Trickle(&raw)).unwrap();
+        assert_eq!(&request.body[..], b"0123456789abc");
+        assert_eq!(request.trailers.get("Checksum"), Some("42"));
+    }
+
+    #[test]
+    fn collects_trailers() {
+        let request = read(&chunked(
+            "3\r\nabc\r\n0\r\nExpires: never\r\nX-Sum: 1\r\n\r\n",
+        ))
+        .unwrap();
+        assert_eq!(request.trailers.len(), 2);
+        assert_eq!(request.trailers.get("x-sum"), Some("1"));
+    }
+
+    #[test]
+    fn rejects_bad_chunk_framing() {
+        for body in [
+            "zz\r\nabc\r\n0\r\n\r\n",
+            "3\r\nabcd\r\n0\r\n\r\n",
+            "\r\nabc\r\n0\r\n\r\n",
+            "3\r\nabc\r\n0\r\nnot a header\r\n\r\n",
+        ] {
+            assert!(
+                matches!(read(&chunked(body)), Err(ReadError::Malformed)),
+                "{:?}",
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_chunks() {
+        let limits = ReadLimits {
+            max_head_size: 8 * 1024,
+            max_body_size: 4,
+        };
+        let raw = chunked("3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n");
+        let result = RequestReader::new(limits).read_request(&mut &raw[..]);
+        assert!(matches!(result, Err(ReadError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn rejects_oversized_trailers() {
+        let limits = ReadLimits {
+            max_head_size: 64,
+            max_body_size: 1024,
+        };
+        let raw = chunked(&format!("0\r\nX-Big: {}\r\n\r\n", "a".repeat(100)));
+        let result = RequestReader::new(limits).read_request(&mut &raw[..]);
+        assert!(matches!(result, Err(ReadError::HeadTooLarge)));
+    }
+
+    #[test]
+    fn checks_transfer_encoding() {
+        let result =
+            read(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n");
+        assert!(matches!(result, Err(ReadError::Malformed)));
+        let result = read(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n");
+        assert!(matches!(result, Err(ReadError::Malformed)));
+        let result = read(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n");
+        assert!(matches!(
+            result,
+            Err(ReadError::UnsupportedTransferEncoding)
+        ));
+    }
+
+    #[test]
+    fn reports_truncated_body() {
+        let result = read(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc");
+        assert!(matches!(result, Err(ReadError::Malformed)));
+    }
+}
@@ -1,6 +1,9 @@
+use bytes::Bytes;
 use std::collections::HashMap;
 
-#[derive(Eq, Hash, PartialEq)]
+use crate::headers::HeaderMap;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum HTTPRequestMethod {
     GET,
     HEAD,
@@ -14,7 +17,54 @@ pub enum HTTPRequestMethod {
     UNKNOWN,
 }
 
+impl HTTPRequestMethod {
+    pub const ALL: [HTTPRequestMethod; 9] = [
+        HTTPRequestMethod::GET,
+        HTTPRequestMethod::HEAD,
+        HTTPRequestMethod::POST,
+        HTTPRequestMethod::PUT,
+        HTTPRequestMethod::DELETE,
+        HTTPRequestMethod::CONNECT,
+        HTTPRequestMethod::OPTIONS,
+        HTTPRequestMethod::TRACE,
+        HTTPRequestMethod::PATCH,
+    ];
+
+    pub fn parse(name: &str) -> HTTPRequestMethod {
+        match name.to_uppercase().as_str() {
+            "GET" => HTTPRequestMethod::GET,
+            "HEAD" => HTTPRequestMethod::HEAD,
+            "POST" => HTTPRequestMethod::POST,
+            "PUT" => HTTPRequestMethod::PUT,
+            "DELETE" => HTTPRequestMethod::DELETE,
+            "CONNECT" => HTTPRequestMethod::CONNECT,
+            "OPTIONS" => HTTPRequestMethod::OPTIONS,
+            "TRACE" => HTTPRequestMethod::TRACE,
+            "PATCH" => HTTPRequestMethod::PATCH,
+            _ => HTTPRequestMethod::UNKNOWN,
+        }
+    }
+}
+
+impl std::fmt::Display for HTTPRequestMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            HTTPRequestMethod::GET => write!(f, "GET"),
+            HTTPRequestMethod::HEAD => write!(f, "HEAD"),
+            HTTPRequestMethod::POST => write!(f, "POST"),
+            HTTPRequestMethod::PUT => write!(f, "PUT"),
+            HTTPRequestMethod::DELETE => write!(f, "DELETE"),
+            HTTPRequestMethod::CONNECT => write!(f, "CONNECT"),
+            HTTPRequestMethod::OPTIONS => write!(f, "OPTIONS"),
+            HTTPRequestMethod::TRACE => write!(f, "TRACE"),
+            HTTPRequestMethod::PATCH => write!(f, "PATCH"),
+            HTTPRequestMethod::UNKNOWN => write!(f, "UNKNOWN"),
+        }
+    }
+}
+
 #[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ENCODINGS {
     URL,
     BASE64,
@@ -56,22 +106,26 @@ pub enum Method {
 pub struct Request {
     pub method: Method,
     pub path: String,
+    pub version: String,
     pub params: HashMap<String, String>,
+    #[allow(dead_code)]
     pub query_string: String,
-    pub body: String,
-    pub headers: Vec<String>,
+    pub body: Bytes,
+    pub headers: HeaderMap,
+    /// Fields sent after the last chunk of a chunked body.
+    #[allow(dead_code)]
+    pub trailers: HeaderMap,
 }
 
 impl Request {
 
     pub fn new(request: &str) -> Request {
-        let mut headers: Vec<String> = Vec::new();
-        let mut body = String::new();
+        let mut headers = HeaderMap::new();
         let mut method = Method::OTHER;
         let _params: HashMap<String,String> = HashMap::new();
         let mut path = String::new();
+        let mut version = String::new();
         let mut query_string = String::new();
-        let mut is_body = false;
         for (i, line) in request.lines().enumerate() {
             if i == 0 {
                 let parts: Vec<&str> = line.split(" ").collect();
@@ -92,25 +146,23 @@ impl Request {
                 if path_parts.len() > 1 {
                     query_string = path_parts[1].to_string();
                 }
-            } else {
-                if line == "" {
-                    is_body = true;
-                } else if is_body {
-                    body = line.to_string();
-                    is_body = false;
-                } else {
-                    headers.push(line.to_string());
-                }
+                version = parts.get(2).unwrap_or(&"HTTP/1.1").to_string();
+            } else if line.is_empty() {
+                break;
+            } else if let Some((name, value)) = HeaderMap::parse_line(line) {
+                headers.append(name, value);
             }
         }
 
         Request {
             method,
             path,
+            version,
             params: _params,
             query_string,
-            body,
+            body: Bytes::new(),
             headers,
+            trailers: HeaderMap::new(),
         }
     }
 
@@ -129,19 +181,39 @@ impl Request {
         }
     }
 
+    /// HTTP/1.1 connections persist unless the client asks for `close`;
+    /// HTTP/1.0 connections only persist on an explicit `keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        if self.version == "HTTP/1.0" {
+            self.headers.has_token("Connection", "keep-alive")
+        } else {
+            !self.headers.has_token("Connection", "close")
+        }
+    }
+
     pub fn with_params(mut self, params: HashMap<String, String>) -> Self {
         self.params = params;
         self
     }
 
+    /// First value of the header `key`, matched case-insensitively.
     pub fn read_header(&self, key: &str) -> Option<String> {
-        for header in &self.headers {
-            let parts: Vec<&str> = header.split(":").collect();
-            if parts[0].trim() == key {
-                return Some(parts[1].trim().to_string());
-            }
-        }
-        None
+        self.headers.get(key).map(|value| value.to_string())
+    }
+
+    #[allow(dead_code)]
+    pub fn text(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.body)
+    }
+
+    #[allow(dead_code)]
+    pub fn json(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::from_slice(&self.body)
+    }
+
+    #[allow(dead_code)]
+    pub fn form(&self) -> Result<HashMap<String, String>, serde_urlencoded::de::Error> {
+        serde_urlencoded::from_bytes(&self.body)
     }
 
 
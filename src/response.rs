@@ -1,81 +1,347 @@
 use std::fmt;
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
 
+use crate::compression::{self, AcceptEncoding, Compression};
+use crate::headers::HeaderMap;
+
+const CHUNK_SIZE: usize = 8192;
+
+/// Every status code in the IANA HTTP Status Code Registry, plus `CUSTOM` for
+/// anything else. `Display` renders the `code reason` pair used in status lines.
 #[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum HTTPResponseStatus {
-    OK = 200,
-    CREATED = 201,
-    ACCEPTED = 202,
-    NOCONTENT = 204,
-    MOVEDPERMANENTLY = 301,
-    FOUND = 302,
-    SEEOTHER = 303,
-    NOTMODIFIED = 304,
-    TEMPORARYREDIRECT = 307,
-    PERMANENTREDIRECT = 308,
-    BADREQUEST = 400,
-    UNAUTHORIZED = 401,
-    FORBIDDEN = 403,
-    NOTFOUND = 404,
-    METHODNOTALLOWED = 405,
-    REQUESTTIMEOUT = 408,
-    CONFLICT = 409,
-    GONE = 410,
-    LENGTHREQUIRED = 411,
-    PAYLOADTOOLARGE = 413,
-    URITOOLONG = 414,
-    UNSUPPORTEDMEDIATYPE = 415,
-    EXPECTATIONFAILED = 417,
-    UPGRADEREQUIRED = 426,
-    INTERNALSERVERERROR = 500,
-    NOTIMPLEMENTED = 501,
-    BADGATEWAY = 502,
+    CONTINUE,
+    SWITCHINGPROTOCOLS,
+    PROCESSING,
+    EARLYHINTS,
+    OK,
+    CREATED,
+    ACCEPTED,
+    NONAUTHORITATIVEINFORMATION,
+    NOCONTENT,
+    RESETCONTENT,
+    PARTIALCONTENT,
+    MULTISTATUS,
+    ALREADYREPORTED,
+    IMUSED,
+    MULTIPLECHOICES,
+    MOVEDPERMANENTLY,
+    FOUND,
+    SEEOTHER,
+    NOTMODIFIED,
+    USEPROXY,
+    TEMPORARYREDIRECT,
+    PERMANENTREDIRECT,
+    BADREQUEST,
+    UNAUTHORIZED,
+    PAYMENTREQUIRED,
+    FORBIDDEN,
+    NOTFOUND,
+    METHODNOTALLOWED,
+    NOTACCEPTABLE,
+    PROXYAUTHENTICATIONREQUIRED,
+    REQUESTTIMEOUT,
+    CONFLICT,
+    GONE,
+    LENGTHREQUIRED,
+    PRECONDITIONFAILED,
+    PAYLOADTOOLARGE,
+    URITOOLONG,
+    UNSUPPORTEDMEDIATYPE,
+    RANGENOTSATISFIABLE,
+    EXPECTATIONFAILED,
+    MISDIRECTEDREQUEST,
+    UNPROCESSABLECONTENT,
+    LOCKED,
+    FAILEDDEPENDENCY,
+    TOOEARLY,
+    UPGRADEREQUIRED,
+    PRECONDITIONREQUIRED,
+    TOOMANYREQUESTS,
+    REQUESTHEADERFIELDSTOOLARGE,
+    UNAVAILABLEFORLEGALREASONS,
+    INTERNALSERVERERROR,
+    NOTIMPLEMENTED,
+    BADGATEWAY,
+    SERVICEUNAVAILABLE,
+    GATEWAYTIMEOUT,
+    HTTPVERSIONNOTSUPPORTED,
+    VARIANTALSONEGOTIATES,
+    INSUFFICIENTSTORAGE,
+    LOOPDETECTED,
+    NOTEXTENDED,
+    NETWORKAUTHENTICATIONREQUIRED,
+    CUSTOM(u16, String),
+}
+
+#[allow(dead_code)]
+impl HTTPResponseStatus {
+    pub fn from_code(code: u16) -> HTTPResponseStatus {
+        match code {
+            100 => HTTPResponseStatus::CONTINUE,
+            101 => HTTPResponseStatus::SWITCHINGPROTOCOLS,
+            102 => HTTPResponseStatus::PROCESSING,
+            103 => HTTPResponseStatus::EARLYHINTS,
+            200 => HTTPResponseStatus::OK,
+            201 => HTTPResponseStatus::CREATED,
+            202 => HTTPResponseStatus::ACCEPTED,
+            203 => HTTPResponseStatus::NONAUTHORITATIVEINFORMATION,
+            204 => HTTPResponseStatus::NOCONTENT,
+            205 => HTTPResponseStatus::RESETCONTENT,
+            206 => HTTPResponseStatus::PARTIALCONTENT,
+            207 => HTTPResponseStatus::MULTISTATUS,
+            208 => HTTPResponseStatus::ALREADYREPORTED,
+            226 => HTTPResponseStatus::IMUSED,
+            300 => HTTPResponseStatus::MULTIPLECHOICES,
+            301 => HTTPResponseStatus::MOVEDPERMANENTLY,
+            302 => HTTPResponseStatus::FOUND,
+            303 => HTTPResponseStatus::SEEOTHER,
+            304 => HTTPResponseStatus::NOTMODIFIED,
+            305 => HTTPResponseStatus::USEPROXY,
+            307 => HTTPResponseStatus::TEMPORARYREDIRECT,
+            308 => HTTPResponseStatus::PERMANENTREDIRECT,
+            400 => HTTPResponseStatus::BADREQUEST,
+            401 => HTTPResponseStatus::UNAUTHORIZED,
+            402 => HTTPResponseStatus::PAYMENTREQUIRED,
+            403 => HTTPResponseStatus::FORBIDDEN,
+            404 => HTTPResponseStatus::NOTFOUND,
+            405 => HTTPResponseStatus::METHODNOTALLOWED,
+            406 => HTTPResponseStatus::NOTACCEPTABLE,
+            407 => HTTPResponseStatus::PROXYAUTHENTICATIONREQUIRED,
+            408 => HTTPResponseStatus::REQUESTTIMEOUT,
+            409 => HTTPResponseStatus::CONFLICT,
+            410 => HTTPResponseStatus::GONE,
+            411 => HTTPResponseStatus::LENGTHREQUIRED,
+            412 => HTTPResponseStatus::PRECONDITIONFAILED,
+            413 => HTTPResponseStatus::PAYLOADTOOLARGE,
+            414 => HTTPResponseStatus::URITOOLONG,
+            415 => HTTPResponseStatus::UNSUPPORTEDMEDIATYPE,
+            416 => HTTPResponseStatus::RANGENOTSATISFIABLE,
+            417 => HTTPResponseStatus::EXPECTATIONFAILED,
+            421 => HTTPResponseStatus::MISDIRECTEDREQUEST,
+            422 => HTTPResponseStatus::UNPROCESSABLECONTENT,
+            423 => HTTPResponseStatus::LOCKED,
+            424 => HTTPResponseStatus::FAILEDDEPENDENCY,
+            425 => HTTPResponseStatus::TOOEARLY,
+            426 => HTTPResponseStatus::UPGRADEREQUIRED,
+            428 => HTTPResponseStatus::PRECONDITIONREQUIRED,
+            429 => HTTPResponseStatus::TOOMANYREQUESTS,
+            431 => HTTPResponseStatus::REQUESTHEADERFIELDSTOOLARGE,
+            451 => HTTPResponseStatus::UNAVAILABLEFORLEGALREASONS,
+            500 => HTTPResponseStatus::INTERNALSERVERERROR,
+            501 => HTTPResponseStatus::NOTIMPLEMENTED,
+            502 => HTTPResponseStatus::BADGATEWAY,
+            503 => HTTPResponseStatus::SERVICEUNAVAILABLE,
+            504 => HTTPResponseStatus::GATEWAYTIMEOUT,
+            505 => HTTPResponseStatus::HTTPVERSIONNOTSUPPORTED,
+            506 => HTTPResponseStatus::VARIANTALSONEGOTIATES,
+            507 => HTTPResponseStatus::INSUFFICIENTSTORAGE,
+            508 => HTTPResponseStatus::LOOPDETECTED,
+            510 => HTTPResponseStatus::NOTEXTENDED,
+            511 => HTTPResponseStatus::NETWORKAUTHENTICATIONREQUIRED,
+            _ => HTTPResponseStatus::CUSTOM(code, class_reason(code).to_string()),
+        }
+    }
+
+    pub fn code(&self) -> u16 {
+        self.parts().0
+    }
+
+    pub fn reason(&self) -> &str {
+        self.parts().1
+    }
+
+    fn parts(&self) -> (u16, &str) {
+        match self {
+            HTTPResponseStatus::CONTINUE => (100, "Continue"),
+            HTTPResponseStatus::SWITCHINGPROTOCOLS => (101, "Switching Protocols"),
+            HTTPResponseStatus::PROCESSING => (102, "Processing"),
+            HTTPResponseStatus::EARLYHINTS => (103, "Early Hints"),
+            HTTPResponseStatus::OK => (200, "OK"),
+            HTTPResponseStatus::CREATED => (201, "Created"),
+            HTTPResponseStatus::ACCEPTED => (202, "Accepted"),
+            HTTPResponseStatus::NONAUTHORITATIVEINFORMATION => {
+                (203, "Non-Authoritative Information")
+            }
+            HTTPResponseStatus::NOCONTENT => (204, "No Content"),
+            HTTPResponseStatus::RESETCONTENT => (205, "Reset Content"),
+            HTTPResponseStatus::PARTIALCONTENT => (206, "Partial Content"),
+            HTTPResponseStatus::MULTISTATUS => (207, "Multi-Status"),
+            HTTPResponseStatus::ALREADYREPORTED => (208, "Already Reported"),
+            HTTPResponseStatus::IMUSED => (226, "IM Used"),
+            HTTPResponseStatus::MULTIPLECHOICES => (300, "Multiple Choices"),
+            HTTPResponseStatus::MOVEDPERMANENTLY => (301, "Moved Permanently"),
+            HTTPResponseStatus::FOUND => (302, "Found"),
+            HTTPResponseStatus::SEEOTHER => (303, "See Other"),
+            HTTPResponseStatus::NOTMODIFIED => (304, "Not Modified"),
+            HTTPResponseStatus::USEPROXY => (305, "Use Proxy"),
+            HTTPResponseStatus::TEMPORARYREDIRECT => (307, "Temporary Redirect"),
+            HTTPResponseStatus::PERMANENTREDIRECT => (308, "Permanent Redirect"),
+            HTTPResponseStatus::BADREQUEST => (400, "Bad Request"),
+            HTTPResponseStatus::UNAUTHORIZED => (401, "Unauthorized"),
+            HTTPResponseStatus::PAYMENTREQUIRED => (402, "Payment Required"),
+            HTTPResponseStatus::FORBIDDEN => (403, "Forbidden"),
+            HTTPResponseStatus::NOTFOUND => (404, "Not Found"),
+            HTTPResponseStatus::METHODNOTALLOWED => (405, "Method Not Allowed"),
+            HTTPResponseStatus::NOTACCEPTABLE => (406, "Not Acceptable"),
+            HTTPResponseStatus::PROXYAUTHENTICATIONREQUIRED => {
+                (407, "Proxy Authentication Required")
+            }
+            HTTPResponseStatus::REQUESTTIMEOUT => (408, "Request Timeout"),
+            HTTPResponseStatus::CONFLICT => (409, "Conflict"),
+            HTTPResponseStatus::GONE => (410, "Gone"),
+            HTTPResponseStatus::LENGTHREQUIRED => (411, "Length Required"),
+            HTTPResponseStatus::PRECONDITIONFAILED => (412, "Precondition Failed"),
+            HTTPResponseStatus::PAYLOADTOOLARGE => (413, "Payload Too Large"),
+            HTTPResponseStatus::URITOOLONG => (414, "URI Too Long"),
+            HTTPResponseStatus::UNSUPPORTEDMEDIATYPE => (415, "Unsupported Media Type"),
+            HTTPResponseStatus::RANGENOTSATISFIABLE => (416, "Range Not Satisfiable"),
+            HTTPResponseStatus::EXPECTATIONFAILED => (417, "Expectation Failed"),
+            HTTPResponseStatus::MISDIRECTEDREQUEST => (421, "Misdirected Request"),
+            HTTPResponseStatus::UNPROCESSABLECONTENT => (422, "Unprocessable Content"),
+            HTTPResponseStatus::LOCKED => (423, "Locked"),
+            HTTPResponseStatus::FAILEDDEPENDENCY => (424, "Failed Dependency"),
+            HTTPResponseStatus::TOOEARLY => (425, "Too Early"),
+            HTTPResponseStatus::UPGRADEREQUIRED => (426, "Upgrade Required"),
+            HTTPResponseStatus::PRECONDITIONREQUIRED => (428, "Precondition Required"),
+            HTTPResponseStatus::TOOMANYREQUESTS => (429, "Too Many Requests"),
+            HTTPResponseStatus::REQUESTHEADERFIELDSTOOLARGE => {
+                (431, "Request Header Fields Too Large")
+            }
+            HTTPResponseStatus::UNAVAILABLEFORLEGALREASONS => {
+                (451, "Unavailable For Legal Reasons")
+            }
+            HTTPResponseStatus::INTERNALSERVERERROR => (500, "Internal Server Error"),
+            HTTPResponseStatus::NOTIMPLEMENTED => (501, "Not Implemented"),
+            HTTPResponseStatus::BADGATEWAY => (502, "Bad Gateway"),
+            HTTPResponseStatus::SERVICEUNAVAILABLE => (503, "Service Unavailable"),
+            HTTPResponseStatus::GATEWAYTIMEOUT => (504, "Gateway Timeout"),
+            HTTPResponseStatus::HTTPVERSIONNOTSUPPORTED => (505, "HTTP Version Not Supported"),
+            HTTPResponseStatus::VARIANTALSONEGOTIATES => (506, "Variant Also Negotiates"),
+            HTTPResponseStatus::INSUFFICIENTSTORAGE => (507, "Insufficient Storage"),
+            HTTPResponseStatus::LOOPDETECTED => (508, "Loop Detected"),
+            HTTPResponseStatus::NOTEXTENDED => (510, "Not Extended"),
+            HTTPResponseStatus::NETWORKAUTHENTICATIONREQUIRED => {
+                (511, "Network Authentication Required")
+            }
+            // A code outside 100-599 is not a valid status, and sending it
+            // anyway could leave the client waiting, so it is a server error.
+            HTTPResponseStatus::CUSTOM(code, _) if !(100..=599).contains(code) => {
+                (500, "Internal Server Error")
+            }
+            HTTPResponseStatus::CUSTOM(code, reason) if reason.is_empty() => {
+                (*code, class_reason(*code))
+            }
+            HTTPResponseStatus::CUSTOM(code, reason) => (*code, reason.as_str()),
+        }
+    }
+}
+
+/// A reason phrase for a code with no registered one, named after its class.
+fn class_reason(code: u16) -> &'static str {
+    match code {
+        100..=199 => "Informational",
+        200..=299 => "Success",
+        300..=399 => "Redirection",
+        400..=499 => "Client Error",
+        _ => "Server Error",
+    }
 }
 
 impl fmt::Display for HTTPResponseStatus {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            HTTPResponseStatus::OK => write!(f, "200 OK"),
-            HTTPResponseStatus::CREATED => write!(f, "201 Created"),
-            HTTPResponseStatus::ACCEPTED => write!(f, "202 Accepted"),
-            HTTPResponseStatus::NOCONTENT => write!(f, "204 No Content"),
-            HTTPResponseStatus::MOVEDPERMANENTLY => write!(f, "301 Moved Permanently"),
-            HTTPResponseStatus::FOUND => write!(f, "302 Found"),
-            HTTPResponseStatus::SEEOTHER => write!(f, "303 See Other"),
-            HTTPResponseStatus::NOTMODIFIED => write!(f, "304 Not Modified"),
-            HTTPResponseStatus::TEMPORARYREDIRECT => write!(f, "307 Temporary Redirect"),
-            HTTPResponseStatus::PERMANENTREDIRECT => write!(f, "308 Permanent Redirect"),
-            HTTPResponseStatus::BADREQUEST => write!(f, "400 Bad Request"),
-            HTTPResponseStatus::UNAUTHORIZED => write!(f, "401 Unauthorized"),
-            HTTPResponseStatus::FORBIDDEN => write!(f, "403 Forbidden"),
-            HTTPResponseStatus::NOTFOUND => write!(f, "404 Not Found"),
-            HTTPResponseStatus::METHODNOTALLOWED => write!(f, "405 Method Not Allowed"),
-            HTTPResponseStatus::REQUESTTIMEOUT => write!(f, "408 Request Timeout"),
-            HTTPResponseStatus::CONFLICT => write!(f, "409 Conflict"),
-            HTTPResponseStatus::GONE => write!(f, "410 Gone"),
-            HTTPResponseStatus::LENGTHREQUIRED => write!(f, "411 Length Required"),
-            HTTPResponseStatus::PAYLOADTOOLARGE => write!(f, "413 Payload Too Large"),
-            HTTPResponseStatus::URITOOLONG => write!(f, "414 URI Too Long"),
-            HTTPResponseStatus::UNSUPPORTEDMEDIATYPE => write!(f, "415 Unsupported Media Type"),
-            HTTPResponseStatus::EXPECTATIONFAILED => write!(f, "417 Expectation Failed"),
-            HTTPResponseStatus::UPGRADEREQUIRED => write!(f, "426 Upgrade Required"),
-            HTTPResponseStatus::INTERNALSERVERERROR => write!(f, "500 Internal Server Error"),
-            HTTPResponseStatus::NOTIMPLEMENTED => write!(f, "501 Not Implemented"),
-            HTTPResponseStatus::BADGATEWAY => write!(f, "502 Bad Gateway"),
-        }
+        let (code, reason) = self.parts();
+        // The reason phrase may not contain control characters that would
+        // break the status line.
+        let reason: String = reason.chars().filter(|c| !c.is_control()).collect();
+        write!(f, "{} {}", code, reason)
     }
 }
 
 pub enum Body {
     Text(String),
     Binary(Vec<u8>),
+    /// Read and sent piece by piece as the response goes out, using
+    /// `Transfer-Encoding: chunked` unless a `Content-Length` was set.
+    Stream(Box<dyn Read + Send>),
+}
+
+impl Body {
+    pub fn stream<R: Read + Send + 'static>(reader: R) -> Body {
+        Body::Stream(Box::new(reader))
+    }
+
+    /// Length in bytes, or `None` for a stream whose length is not known
+    /// until it has been read.
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            Body::Text(body) => Some(body.len()),
+            Body::Binary(data) => Some(data.len()),
+            Body::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+}
+
+/// Where a response is written when it is sent. Blocking connections write
+/// straight to the socket; async connections collect the bytes and write them
+/// once the handler's future completes. A streamed body on an async
+/// connection is passed a piece at a time through a channel instead, so it is
+/// never held in memory whole.
+pub enum Output {
+    Stream(std::net::TcpStream),
+    Buffer(Vec<u8>),
+    Channel(tokio::sync::mpsc::Sender<Vec<u8>>),
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Stream(stream) => stream.write(buf),
+            Output::Buffer(buffer) => buffer.write(buf),
+            // Blocks while the channel is full, which holds the reader back to
+            // the pace of the socket. A closed channel means the connection
+            // has gone.
+            Output::Channel(sender) => match sender.blocking_send(buf.to_vec()) {
+                Ok(()) => Ok(buf.len()),
+                Err(_) => Err(io::ErrorKind::BrokenPipe.into()),
+            },
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Stream(stream) => stream.flush(),
+            Output::Buffer(_) | Output::Channel(_) => Ok(()),
+        }
+    }
 }
 
 pub struct Response {
-    pub status: String,
+    pub status: HTTPResponseStatus,
     pub body: Body,
-    pub headers: Vec<String>,
-    pub stream: std::net::TcpStream,
+    pub headers: HeaderMap,
+    pub keep_alive: bool,
+    /// Set for HEAD requests: the status line and headers are sent as usual,
+    /// including `Content-Length`, but the body is not.
+    pub head_only: bool,
+    /// Whether the client understands chunked responses. HTTP/1.0 clients do
+    /// not, so a streamed body is sent raw and the connection closed after it.
+    pub chunked: bool,
+    /// Sent after the last chunk of a chunked response.
+    pub trailers: HeaderMap,
+    /// Compression applied when the response is sent, if the client accepts
+    /// it. `None` sends the body as is.
+    pub compression: Option<Compression>,
+    pub accept_encoding: AcceptEncoding,
+    sent: bool,
+    /// Set while a streamed body is still to be written after the head.
+    stream_pending: bool,
+    pub output: Output,
 }
 
 impl fmt::Display for Response {
@@ -87,119 +353,345 @@ impl fmt::Display for Response {
             match &self.body {
                 Body::Text(body) => body,
                 Body::Binary(_) => "Binary data",
+                Body::Stream(_) => "Stream",
             }
         )
     }
 }
 
 impl Response {
-    pub fn new(stream: &mut std::net::TcpStream) -> Response {
+    /// A response written straight to `stream`, usually a clone of the
+    /// connection's socket.
+    pub fn new(stream: std::net::TcpStream) -> Response {
+        Response {
+            headers: HeaderMap::new(),
+            body: Body::Text("".to_string()),
+            status: HTTPResponseStatus::OK,
+            keep_alive: true,
+            head_only: false,
+            chunked: true,
+            trailers: HeaderMap::new(),
+            compression: None,
+            accept_encoding: AcceptEncoding::default(),
+            sent: false,
+            stream_pending: false,
+            output: Output::Stream(stream),
+        }
+    }
+
+    pub fn buffered() -> Response {
         Response {
-            headers: Vec::new(),
+            headers: HeaderMap::new(),
             body: Body::Text("".to_string()),
-            status: "200 OK".to_string(),
-            stream: stream.try_clone().unwrap(),
+            status: HTTPResponseStatus::OK,
+            keep_alive: true,
+            head_only: false,
+            chunked: true,
+            trailers: HeaderMap::new(),
+            compression: None,
+            accept_encoding: AcceptEncoding::default(),
+            sent: false,
+            stream_pending: false,
+            output: Output::Buffer(Vec::new()),
+        }
+    }
+
+    /// Takes the bytes written so far by a buffered response.
+    pub fn take_buffer(&mut self) -> Vec<u8> {
+        match &mut self.output {
+            Output::Buffer(buffer) => std::mem::take(buffer),
+            Output::Stream(_) | Output::Channel(_) => Vec::new(),
+        }
+    }
+
+    /// Whether `send` wrote only the head of a streamed body to the buffer,
+    /// leaving the body for `send_stream`.
+    pub fn has_pending_stream(&self) -> bool {
+        self.stream_pending
+    }
+
+    /// Status line and headers, including the `Connection` header derived from
+    /// `keep_alive`. A handler-supplied `Connection: close` also turns
+    /// `keep_alive` off so the connection loop stops after this response.
+    fn head(&mut self) -> String {
+        self.set_framing();
+
+        let connection_set = self.headers.contains("Connection");
+        if self.headers.has_token("Connection", "close") {
+            self.keep_alive = false;
+        }
+
+        let mut head = format!("{}\r\n", self.get_status_line());
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !connection_set {
+            if self.keep_alive {
+                head.push_str("Connection: keep-alive\r\n");
+            } else {
+                head.push_str("Connection: close\r\n");
+            }
         }
+        head.push_str("\r\n");
+        head
     }
 
+    /// Decides how the body is delimited. `Content-Length` always reflects a
+    /// buffered body, so any value a handler set is replaced, unless the
+    /// response is to a HEAD request and has no body; streams are chunked
+    /// unless the handler declared their length. Nothing is declared for
+    /// statuses that never carry a body.
+    fn set_framing(&mut self) {
+        let code = self.status.code();
+        if code < 200 || code == 204 || code == 304 {
+            self.headers.remove("Content-Length");
+            self.headers.remove("Transfer-Encoding");
+            return;
+        }
+
+        let wants_chunked = self.headers.has_token("Transfer-Encoding", "chunked")
+            || (matches!(self.body, Body::Stream(_)) && !self.headers.contains("Content-Length"));
+        if wants_chunked {
+            self.headers.remove("Content-Length");
+            if self.chunked {
+                self.headers.insert("Transfer-Encoding", "chunked");
+                if !self.trailers.is_empty() {
+                    let names: Vec<&str> = self.trailers.iter().map(|(name, _)| name).collect();
+                    self.headers.insert("Trailer", names.join(", "));
+                }
+                return;
+            }
+            self.headers.remove("Transfer-Encoding");
+        }
+
+        // An explicit HEAD handler sends no body but may declare the length
+        // of the GET representation.
+        if self.head_only && self.body.is_empty() && self.headers.content_length().is_some() {
+            return;
+        }
+        match self.body.len() {
+            Some(length) => {
+                if let Some(declared) = self.headers.content_length() {
+                    if declared != length {
+                        println!(
+                            "warning: replacing Content-Length {} with actual body length {}",
+                            declared, length
+                        );
+                    }
+                }
+                self.headers.insert("Content-Length", length.to_string());
+            }
+            // A stream the client cannot take chunked ends when the
+            // connection does.
+            None if !self.headers.contains("Content-Length") => self.keep_alive = false,
+            None => {}
+        }
+    }
+
+    /// Writes the status line, headers and body to the output. A response is
+    /// only ever sent once; later calls are ignored. Write errors are logged
+    /// and turn `keep_alive` off, so the connection loop closes the stream
+    /// instead of panicking.
     pub fn send(&mut self) {
-        let response = format!(
-            "HTTP/1.1 {}\r\n{}\r\n\r\n{}",
-            self.status,
-            self.headers.join("\r\n"),
-            match &self.body {
-                Body::Text(body) => body.clone(),
-                Body::Binary(data) => {
-                    let body: String = data.iter().map(|&byte| byte as char).collect();
-                    body
+        if self.sent {
+            println!("warning: response already sent");
+            return;
+        }
+        self.sent = true;
+
+        let written = self.write();
+        self.check(written);
+    }
+
+    /// Writes a streamed body held back by a buffered `send`, to `output`.
+    /// This blocks on the stream, so async connections run it off the
+    /// runtime.
+    pub fn send_stream(&mut self, output: Output) {
+        self.output = output;
+        self.stream_pending = false;
+        let written = self.write_body();
+        // Dropping the output tells a channel's receiver the body is done.
+        self.output = Output::Buffer(Vec::new());
+        self.check(written);
+    }
+
+    fn check(&mut self, written: io::Result<()>) {
+        if let Err(e) = written {
+            self.stream_pending = false;
+            if !matches!(
+                e.kind(),
+                io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+            ) {
+                println!("error: {}", e);
+            }
+            self.keep_alive = false;
+        }
+    }
+
+    pub fn is_sent(&self) -> bool {
+        self.sent
+    }
+
+    fn write(&mut self) -> io::Result<()> {
+        compression::compress(self)?;
+        let head = self.head();
+        self.output.write_all(head.as_bytes())?;
+        if self.head_only {
+            return self.output.flush();
+        }
+        // A buffer must not take a stream whole; the connection loop sends it
+        // on once the head is out.
+        if matches!(self.output, Output::Buffer(_)) && matches!(self.body, Body::Stream(_)) {
+            self.stream_pending = true;
+            return Ok(());
+        }
+        self.write_body()
+    }
+
+    fn write_body(&mut self) -> io::Result<()> {
+        let chunked = self.headers.has_token("Transfer-Encoding", "chunked");
+        let body = std::mem::replace(&mut self.body, Body::Text(String::new()));
+        match body {
+            Body::Text(body) if chunked => self.write_chunks(&mut body.as_bytes())?,
+            Body::Binary(data) if chunked => self.write_chunks(&mut data.as_slice())?,
+            Body::Stream(mut reader) if chunked => self.write_chunks(&mut reader)?,
+            Body::Text(body) => self.output.write_all(body.as_bytes())?,
+            Body::Binary(data) => self.output.write_all(&data)?,
+            Body::Stream(reader) => {
+                let declared = self.headers.content_length();
+                let limit = declared.map_or(u64::MAX, |length| length as u64);
+                let copied = io::copy(&mut reader.take(limit), &mut self.output)?;
+                if declared.is_some_and(|length| copied < length as u64) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "body stream ended before its Content-Length",
+                    ));
                 }
             }
-        );
-        self.stream.write(response.as_bytes()).unwrap();
-        self.stream.flush().unwrap();
-    }
-
-    pub fn send_binary(&mut self) {
-        // let headers_bytes: Vec<u8> = self.headers.join("\r\n").into_bytes();
-        // let response = format!(
-        //     "HTTP/1.1 {}\r\n{}\r\n\r\n{}",
-        //     self.status,
-        //     String::from_utf8_lossy(&headers_bytes), // Convert headers bytes back to string
-        //     match &self.body {
-        //         Body::Text(body) => body.clone(),
-        //         Body::Binary(data) => {
-        //             let body: String = data.iter().map(|&byte| byte as char).collect();
-        //             body
-        //         }
-        //     }
-        // );
-        // self.stream.write(response.as_bytes()).unwrap();
-        // self.stream.flush().unwrap();
-
-        let mut response = [
-            self.status.clone(),
-            self.headers.join("\r\n"),
-            "\r\n\r\n".to_string(),
-        ]
-        .join("\r\n")
-        .into_bytes();
-        response.extend(match &self.body {
-            Body::Text(body) => body.clone().into_bytes(),
-            Body::Binary(data) => data.clone(),
-        });
-        self.stream.write("HTTP/1.1 ".as_bytes()).unwrap();
-
-        self.stream.write(&response).unwrap();
-        self.stream.flush().unwrap();
-    }
-
-    pub fn get_status_line(status: HTTPResponseStatus) -> String {
-        match status {
-            HTTPResponseStatus::OK => "200 OK".to_string(),
-            HTTPResponseStatus::CREATED => "201 Created".to_string(),
-            HTTPResponseStatus::ACCEPTED => "202 Accepted".to_string(),
-            HTTPResponseStatus::NOCONTENT => "204 No Content".to_string(),
-            HTTPResponseStatus::MOVEDPERMANENTLY => "301 Moved Permanently".to_string(),
-            HTTPResponseStatus::FOUND => "302 Found".to_string(),
-            HTTPResponseStatus::SEEOTHER => "303 See Other".to_string(),
-            HTTPResponseStatus::NOTMODIFIED => "304 Not Modified".to_string(),
-            HTTPResponseStatus::TEMPORARYREDIRECT => "307 Temporary Redirect".to_string(),
-            HTTPResponseStatus::PERMANENTREDIRECT => "308 Permanent Redirect".to_string(),
-            HTTPResponseStatus::BADREQUEST => "400 Bad Request".to_string(),
-            HTTPResponseStatus::UNAUTHORIZED => "401 Unauthorized".to_string(),
-            HTTPResponseStatus::FORBIDDEN => "403 Forbidden".to_string(),
-            HTTPResponseStatus::NOTFOUND => "404 Not Found".to_string(),
-            HTTPResponseStatus::METHODNOTALLOWED => "405 Method Not Allowed".to_string(),
-            HTTPResponseStatus::REQUESTTIMEOUT => "408 Request Timeout".to_string(),
-            HTTPResponseStatus::CONFLICT => "409 Conflict".to_string(),
-            HTTPResponseStatus::GONE => "410 Gone".to_string(),
-            HTTPResponseStatus::LENGTHREQUIRED => "411 Length Required".to_string(),
-            HTTPResponseStatus::PAYLOADTOOLARGE => "413 Payload Too Large".to_string(),
-            HTTPResponseStatus::URITOOLONG => "414 URI Too Long".to_string(),
-            HTTPResponseStatus::UNSUPPORTEDMEDIATYPE => "415 Unsupported Media Type".to_string(),
-            HTTPResponseStatus::EXPECTATIONFAILED => "417 Expectation Failed".to_string(),
-            HTTPResponseStatus::UPGRADEREQUIRED => "426 Upgrade Required".to_string(),
-            HTTPResponseStatus::INTERNALSERVERERROR => "500 Internal Server Error".to_string(),
-            HTTPResponseStatus::NOTIMPLEMENTED => "501 Not Implemented".to_string(),
-            HTTPResponseStatus::BADGATEWAY => "502 Bad Gateway".to_string(),
         }
+        self.output.flush()
+    }
+
+    /// Copies `reader` out as chunks, then the terminating chunk and trailers.
+    /// A read error part way through leaves the message unterminated, so the
+    /// client can tell it was cut short.
+    fn write_chunks(&mut self, reader: &mut dyn Read) -> io::Result<()> {
+        let mut buffer = [0; CHUNK_SIZE];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.output
+                .write_all(format!("{:X}\r\n", read).as_bytes())?;
+            self.output.write_all(&buffer[..read])?;
+            self.output.write_all(b"\r\n")?;
+        }
+
+        let mut end = String::from("0\r\n");
+        for (name, value) in self.trailers.iter() {
+            end.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        end.push_str("\r\n");
+        self.output.write_all(end.as_bytes())
+    }
+
+    /// The full status line, e.g. `HTTP/1.1 404 Not Found`.
+    pub fn get_status_line(&self) -> String {
+        format!("HTTP/1.1 {}", self.status)
     }
 
     #[allow(dead_code)]
     pub fn set_status(&mut self, status: HTTPResponseStatus) -> &mut Self {
-        self.status = Response::get_status_line(status);
-        return self;
+        self.status = status;
+        self
     }
 
     #[allow(dead_code)]
     pub fn set_body(&mut self, body: Body) -> &mut Self {
         self.body = body;
-        return self;
+        self
     }
 
     #[allow(dead_code)]
-    pub fn add_header(&mut self, header: String) -> &mut Self {
-        self.headers.push(header);
-        return self;
+    pub fn add_header(&mut self, name: &str, value: &str) -> &mut Self {
+        self.headers.append(name, value);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn renders_registered_statuses() {
+        assert_eq!(HTTPResponseStatus::OK.to_string(), "200 OK");
+        assert_eq!(
+            HTTPResponseStatus::from_code(404).to_string(),
+            "404 Not Found"
+        );
+    }
+
+    #[test]
+    fn names_unregistered_codes_by_class() {
+        assert_eq!(
+            HTTPResponseStatus::from_code(299).to_string(),
+            "299 Success"
+        );
+        assert_eq!(
+            HTTPResponseStatus::CUSTOM(499, String::new()).to_string(),
+            "499 Client Error"
+        );
+        assert_eq!(
+            HTTPResponseStatus::CUSTOM(418, "I'm a teapot\r\n".to_string()).to_string(),
+            "418 I'm a teapot"
+        );
+    }
+
+    fn sent(response: &mut Response) -> String {
+        response.send();
+        String::from_utf8(response.take_buffer()).unwrap()
+    }
+
+    #[test]
+    fn replaces_declared_length_with_body_length() {
+        let mut response = Response::buffered();
+        response.body = Body::Text("hello".to_string());
+        response.headers.insert("Content-Length", "99");
+        assert!(sent(&mut response).contains("Content-Length: 5\r\n"));
+    }
+
+    #[test]
+    fn head_keeps_declared_length() {
+        let mut response = Response::buffered();
+        response.head_only = true;
+        response.headers.insert("Content-Length", "1234");
+        let sent = sent(&mut response);
+        assert!(sent.contains("Content-Length: 1234\r\n"));
+        assert!(sent.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn head_counts_body_it_does_not_send() {
+        let mut response = Response::buffered();
+        response.head_only = true;
+        response.body = Body::Text("hello".to_string());
+        let sent = sent(&mut response);
+        assert!(sent.contains("Content-Length: 5\r\n"));
+        assert!(!sent.contains("hello"));
+    }
+
+    #[test]
+    fn treats_invalid_codes_as_server_errors() {
+        for code in [0, 42, 99, 600, 999, 1000] {
+            let status = HTTPResponseStatus::CUSTOM(code, "Odd".to_string());
+            assert_eq!(status.code(), 500);
+            assert_eq!(status.to_string(), "500 Internal Server Error");
+            assert_eq!(HTTPResponseStatus::from_code(code).code(), 500);
+        }
     }
 }
@@ -1,8 +1,67 @@
-use std::collections::HashMap;
-use crate::request::{Request, HTTPRequestMethod};
+use crate::request::{HTTPRequestMethod, Request};
 use crate::response::Response;
+use crate::static_files::{ServeOptions, StaticFiles};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+
+pub type SyncHandler = Arc<dyn Fn(Request, &mut Response) + Send + Sync + 'static>;
+pub type AsyncHandler = Arc<
+    dyn Fn(Request, Response) -> Pin<Box<dyn Future<Output = Response> + Send>>
+        + Send
+        + Sync
+        + 'static,
+>;
 
-pub type Handler = Box<dyn FnMut(Request, &mut Response) + Send + 'static>;
+pub enum Resolution<'a> {
+    Found(&'a Handler, HashMap<String, String>),
+    /// The path is registered, but only under the listed methods.
+    MethodNotAllowed(Vec<HTTPRequestMethod>),
+    NotFound,
+}
+
+#[derive(Clone)]
+pub enum Handler {
+    Sync(SyncHandler),
+    /// Takes the response by value and hands it back once done, since the
+    /// future may outlive the caller's stack frame.
+    Async(AsyncHandler),
+}
+
+thread_local! {
+    static RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime for async handlers");
+}
+
+impl Handler {
+    /// Runs the handler on a blocking connection. Async handlers are driven to
+    /// completion on a per-thread runtime.
+    pub fn call(&self, request: Request, response: &mut Response) {
+        match self {
+            Handler::Sync(handler) => handler(request, response),
+            Handler::Async(handler) => {
+                let pending = std::mem::replace(response, Response::buffered());
+                *response = RUNTIME.with(|runtime| runtime.block_on(handler(request, pending)));
+            }
+        }
+    }
+
+    /// Runs the handler on an async connection. Sync handlers may block, so they
+    /// run through `block_in_place`, which needs the multi-threaded runtime.
+    pub async fn call_async(&self, request: Request, mut response: Response) -> Response {
+        match self {
+            Handler::Sync(handler) => {
+                tokio::task::block_in_place(|| handler(request, &mut response));
+                response
+            }
+            Handler::Async(handler) => handler(request, response).await,
+        }
+    }
+}
 
 pub struct Routes {
     pub routes: HashMap<HTTPRequestMethod, HashMap<String, Handler>>,
@@ -17,61 +76,217 @@ impl Routes {
         }
     }
 
-    pub fn get<F>(&mut self, path: &str, handler: F)
+    /// Registers a handler for `method`. Paths containing `:name` segments are
+    /// matched segment by segment and the captured values end up in
+    /// `Request::params`. A final `*name` segment captures the rest of the
+    /// path, slashes included, and also matches when nothing follows.
+    pub fn route<F>(&mut self, method: HTTPRequestMethod, path: &str, handler: F)
+    where
+        F: Fn(Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.add(method, path, Handler::Sync(Arc::new(handler)));
+    }
+
+    pub fn route_async<F, Fut>(&mut self, method: HTTPRequestMethod, path: &str, handler: F)
     where
-        F: FnMut(Request, &mut Response) + Send + 'static
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
     {
-        if path.contains(":") {
-            let route_handlers = self.parameterized_routes.entry(HTTPRequestMethod::GET).or_insert_with(Vec::new);
+        let handler = Handler::Async(Arc::new(move |request, response| {
+            Box::pin(handler(request, response))
+        }));
+        self.add(method, path, handler);
+    }
+
+    fn add(&mut self, method: HTTPRequestMethod, path: &str, handler: Handler) {
+        if path.contains(":") || path.contains("*") {
+            let route_handlers = self.parameterized_routes.entry(method).or_default();
             let parts = path.split("/").map(|part| part.to_string()).collect();
-            route_handlers.push((parts, Box::new(handler)));
+            route_handlers.push((parts, handler));
         } else {
-            let route_handlers = self.routes.entry(HTTPRequestMethod::GET).or_insert_with(HashMap::new);
-            route_handlers.insert(path.to_string(), Box::new(handler));
+            let route_handlers = self.routes.entry(method).or_default();
+            route_handlers.insert(path.to_string(), handler);
         }
     }
 
+    pub fn get<F>(&mut self, path: &str, handler: F)
+    where
+        F: Fn(Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.route(HTTPRequestMethod::GET, path, handler);
+    }
+
+    #[allow(dead_code)]
     pub fn post<F>(&mut self, path: &str, handler: F)
     where
-        F: FnMut(Request, &mut Response) + Send + 'static
+        F: Fn(Request, &mut Response) + Send + Sync + 'static,
     {
-        if path.contains(":") {
-            let route_handlers = self.parameterized_routes.entry(HTTPRequestMethod::POST).or_insert_with(Vec::new);
-            let parts = path.split("/").map(|part| part.to_string()).collect();
-            route_handlers.push((parts, Box::new(handler)));
+        self.route(HTTPRequestMethod::POST, path, handler);
+    }
+
+    #[allow(dead_code)]
+    pub fn put<F>(&mut self, path: &str, handler: F)
+    where
+        F: Fn(Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.route(HTTPRequestMethod::PUT, path, handler);
+    }
+
+    pub fn delete<F>(&mut self, path: &str, handler: F)
+    where
+        F: Fn(Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.route(HTTPRequestMethod::DELETE, path, handler);
+    }
+
+    #[allow(dead_code)]
+    pub fn patch<F>(&mut self, path: &str, handler: F)
+    where
+        F: Fn(Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.route(HTTPRequestMethod::PATCH, path, handler);
+    }
+
+    #[allow(dead_code)]
+    pub fn head<F>(&mut self, path: &str, handler: F)
+    where
+        F: Fn(Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.route(HTTPRequestMethod::HEAD, path, handler);
+    }
+
+    #[allow(dead_code)]
+    pub fn options<F>(&mut self, path: &str, handler: F)
+    where
+        F: Fn(Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.route(HTTPRequestMethod::OPTIONS, path, handler);
+    }
+
+    /// Registers the same handler for every known method.
+    #[allow(dead_code)]
+    pub fn any<F>(&mut self, path: &str, handler: F)
+    where
+        F: Fn(Request, &mut Response) + Send + Sync + 'static,
+    {
+        let handler = Handler::Sync(Arc::new(handler));
+        for method in HTTPRequestMethod::ALL {
+            self.add(method, path, handler.clone());
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get_async<F, Fut>(&mut self, path: &str, handler: F)
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        self.route_async(HTTPRequestMethod::GET, path, handler);
+    }
+
+    pub fn post_async<F, Fut>(&mut self, path: &str, handler: F)
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        self.route_async(HTTPRequestMethod::POST, path, handler);
+    }
+
+    /// Serves the files under `root` at `prefix`, e.g. `/assets` maps
+    /// `/assets/css/site.css` to `root/css/site.css`. May be called once per
+    /// directory to mount.
+    pub fn serve_dir<P: Into<PathBuf>>(&mut self, prefix: &str, root: P, options: ServeOptions) {
+        let prefix = prefix.trim_end_matches('/');
+        let mut files = StaticFiles::new(root);
+        files.prefix = format!("{}/", prefix);
+        files.options = options;
+
+        self.get(&format!("{}/*path", prefix), move |request, response| {
+            let relative = request.params.get("path").cloned().unwrap_or_default();
+            files.serve(&request, &relative, response);
+        });
+    }
+
+    pub fn resolve(&self, method: HTTPRequestMethod, path: &str) -> Resolution<'_> {
+        if let Some((handler, params)) = self.find(method, path) {
+            return Resolution::Found(handler, params);
+        }
+
+        let allowed = self.allowed_methods(path);
+        if allowed.is_empty() {
+            Resolution::NotFound
         } else {
-            let route_handlers = self.routes.entry(HTTPRequestMethod::POST).or_insert_with(HashMap::new);
-            route_handlers.insert(path.to_string(), Box::new(handler));
+            Resolution::MethodNotAllowed(allowed)
+        }
+    }
+
+    /// Every method with a handler registered for `path`. OPTIONS is listed
+    /// for any registered path since the server answers it automatically.
+    pub fn allowed_methods(&self, path: &str) -> Vec<HTTPRequestMethod> {
+        let mut allowed: Vec<HTTPRequestMethod> = HTTPRequestMethod::ALL
+            .into_iter()
+            .filter(|method| self.find(*method, path).is_some())
+            .collect();
+        if !allowed.is_empty() && !allowed.contains(&HTTPRequestMethod::OPTIONS) {
+            allowed.push(HTTPRequestMethod::OPTIONS);
         }
+        allowed
     }
 
-    pub fn resolve(&mut self, method: HTTPRequestMethod, path: &str) -> Option<(&mut Handler, HashMap<String, String>)> {
-        if let Some(route_handlers) = self.routes.get_mut(&method) {
-            if let Some(handler) = route_handlers.get_mut(path) {
+    fn find(
+        &self,
+        method: HTTPRequestMethod,
+        path: &str,
+    ) -> Option<(&Handler, HashMap<String, String>)> {
+        if let Some(route_handlers) = self.routes.get(&method) {
+            if let Some(handler) = route_handlers.get(path) {
                 return Some((handler, HashMap::new()));
             }
         }
 
-        if let Some(handlers) = self.parameterized_routes.get_mut(&method) {
-            for (parts, handler) in handlers.iter_mut() {
+        if let Some(handlers) = self.parameterized_routes.get(&method) {
+            for (parts, handler) in handlers.iter() {
                 if let Some(params) = Routes::match_parameterized_route(path, parts) {
                     return Some((handler, params));
                 }
             }
         }
 
+        // HEAD is answered by the GET handler unless it has its own route; the
+        // server drops the body before it goes out.
+        if method == HTTPRequestMethod::HEAD {
+            return self.find(HTTPRequestMethod::GET, path);
+        }
+
         None
     }
 
     fn match_parameterized_route(path: &str, parts: &[String]) -> Option<HashMap<String, String>> {
         let path_parts: Vec<&str> = path.split("/").collect();
+        let mut params: HashMap<String, String> = HashMap::new();
+        if let Some(rest_name) = parts.last().and_then(|part| part.strip_prefix("*")) {
+            let fixed = parts.len() - 1;
+            if path_parts.len() < fixed {
+                return None;
+            }
+            let rest = path_parts.get(fixed..).unwrap_or_default().join("/");
+            params.insert(rest_name.to_string(), rest);
+            return Routes::match_segments(&path_parts[..fixed], &parts[..fixed], params);
+        }
+
         if path_parts.len() != parts.len() {
             return None;
         }
-        let mut params: HashMap<String, String> = HashMap::new();
+        Routes::match_segments(&path_parts, parts, params)
+    }
+
+    fn match_segments(
+        path_parts: &[&str],
+        parts: &[String],
+        mut params: HashMap<String, String>,
+    ) -> Option<HashMap<String, String>> {
         for (path_part, part) in path_parts.iter().zip(parts.iter()) {
-            if part.starts_with(":") {
-                let part_name = &part[1..];
+            if let Some(part_name) = part.strip_prefix(":") {
                 params.insert(part_name.to_string(), path_part.to_string());
             } else if part != path_part {
                 return None;
@@ -0,0 +1,247 @@
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+
+use crate::async_server;
+use crate::compression::{self, AcceptEncoding, DecodeError};
+use crate::config::{Config, Mode};
+use crate::cors::Cors;
+use crate::reader::{ReadError, RequestReader};
+use crate::request::{HTTPRequestMethod, Request};
+use crate::response::{Body, HTTPResponseStatus, Response};
+use crate::routes::{Handler, Resolution, Routes};
+use crate::thread_pool::ThreadPool;
+
+/// Owns the route table for the lifetime of the process, so handlers and any
+/// state they capture are built once and shared by every connection.
+pub struct Server {
+    pub routes: Routes,
+    pub config: Config,
+}
+
+impl Server {
+    pub fn new(config: Config) -> Server {
+        Server {
+            routes: Routes::new(),
+            config,
+        }
+    }
+
+    pub fn run(self) {
+        let server = Arc::new(self);
+
+        if server.config.mode == Mode::Async {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async_server::run(server));
+            return;
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
+
+        let pool = match server.config.mode {
+            Mode::Single | Mode::Async => None,
+            Mode::Pooled => {
+                println!("Using {} worker threads", server.config.workers);
+                Some(ThreadPool::new(
+                    server.config.workers,
+                    server.config.queue_size,
+                ))
+            }
+        };
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => match &pool {
+                    Some(pool) => {
+                        let server = Arc::clone(&server);
+                        pool.execute(move || server.handle_connection(&mut stream));
+                    }
+                    None => server.handle_connection(&mut stream),
+                },
+                Err(e) => {
+                    println!("error: {}", e);
+                }
+            }
+        }
+    }
+
+    pub fn handle_connection(&self, stream: &mut TcpStream) {
+        // A zero idle timeout means connections are never timed out.
+        let idle_timeout = Some(self.config.idle_timeout).filter(|timeout| !timeout.is_zero());
+        if let Err(e) = stream.set_read_timeout(idle_timeout) {
+            println!("error: {}", e);
+            return;
+        }
+
+        let mut reader = RequestReader::new(self.config.limits);
+        loop {
+            // Each response writes through its own handle on the socket.
+            let output = match stream.try_clone() {
+                Ok(output) => output,
+                Err(e) => {
+                    println!("error: {}", e);
+                    return;
+                }
+            };
+            let request = match reader.read_request(stream) {
+                Ok(request) => request,
+                Err(error) => {
+                    let mut response = Response::new(output);
+                    send_read_error(error, &mut response);
+                    return;
+                }
+            };
+
+            let mut response = Response::new(output);
+            if let Some((handler, request)) = self.dispatch(request, &mut response) {
+                handler.call(request, &mut response);
+            }
+
+            // A handler that returns without sending still owes the client a
+            // response; whatever it set up so far goes out as is.
+            if !response.is_sent() {
+                response.send();
+            }
+
+            if !response.keep_alive {
+                return;
+            }
+        }
+    }
+
+    /// Routes a request and applies everything that happens around the
+    /// handler: connection and HEAD flags, CORS, automatic OPTIONS answers and
+    /// 404/405. Returns the handler left to call, or `None` when the response
+    /// has already been sent.
+    pub fn dispatch(
+        &self,
+        request: Request,
+        response: &mut Response,
+    ) -> Option<(&Handler, Request)> {
+        let method = request.get_method();
+        response.keep_alive = request.keep_alive();
+        response.head_only = method == HTTPRequestMethod::HEAD;
+        response.chunked = request.version != "HTTP/1.0";
+        response.compression = self.config.compression;
+        response.accept_encoding =
+            AcceptEncoding::parse(&request.headers.get_list("Accept-Encoding"));
+
+        if let Some(cors) = &self.config.cors {
+            if Cors::is_preflight(&request) {
+                let allowed = self.routes.allowed_methods(&request.path);
+                if allowed.is_empty() {
+                    send_not_found(response);
+                } else {
+                    cors.preflight(&request, &allowed, response);
+                    send_options(response, &allowed);
+                }
+                return None;
+            }
+            cors.apply(&request, response);
+        }
+
+        match self.routes.resolve(method, &request.path) {
+            Resolution::Found(handler, params) => {
+                let mut request = request.with_params(params);
+                match compression::decompress(&mut request, self.config.limits.max_body_size) {
+                    Ok(()) => Some((handler, request)),
+                    Err(error) => {
+                        send_decode_error(error, response);
+                        None
+                    }
+                }
+            }
+            Resolution::MethodNotAllowed(allowed) if method == HTTPRequestMethod::OPTIONS => {
+                send_options(response, &allowed);
+                None
+            }
+            Resolution::MethodNotAllowed(allowed) => {
+                send_method_not_allowed(response, &allowed);
+                None
+            }
+            Resolution::NotFound if method == HTTPRequestMethod::OPTIONS && request.path == "*" => {
+                send_options(response, &HTTPRequestMethod::ALL);
+                None
+            }
+            Resolution::NotFound => {
+                send_not_found(response);
+                None
+            }
+        }
+    }
+}
+
+/// Answers a request that could not be read. Closed and timed-out connections
+/// get no response at all.
+pub fn send_read_error(error: ReadError, response: &mut Response) {
+    let (status, body) = match error {
+        ReadError::Closed => return,
+        ReadError::Io(e) => {
+            if !matches!(
+                e.kind(),
+                ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::ConnectionReset
+            ) {
+                println!("error: {}", e);
+            }
+            return;
+        }
+        ReadError::HeadTooLarge => (
+            HTTPResponseStatus::REQUESTHEADERFIELDSTOOLARGE,
+            "431 Request Header Fields Too Large",
+        ),
+        ReadError::BodyTooLarge => (HTTPResponseStatus::PAYLOADTOOLARGE, "413 Payload Too Large"),
+        ReadError::Malformed => (HTTPResponseStatus::BADREQUEST, "400 Bad Request"),
+        ReadError::UnsupportedTransferEncoding => {
+            (HTTPResponseStatus::NOTIMPLEMENTED, "501 Not Implemented")
+        }
+    };
+    response.status = status;
+    response.body = Body::Text(body.to_string());
+    response.headers.insert("Content-Type", "text/plain");
+    response.keep_alive = false;
+    response.send();
+}
+
+/// Answers a request whose body could not be decoded. A 415 lists the
+/// codings that are understood.
+pub fn send_decode_error(error: DecodeError, response: &mut Response) {
+    let (status, body) = match error {
+        DecodeError::Unsupported(coding) => {
+            println!("warning: unsupported Content-Encoding {}", coding);
+            response.headers.insert("Accept-Encoding", "gzip, deflate");
+            (
+                HTTPResponseStatus::UNSUPPORTEDMEDIATYPE,
+                "415 Unsupported Media Type",
+            )
+        }
+        DecodeError::TooLarge => (HTTPResponseStatus::PAYLOADTOOLARGE, "413 Payload Too Large"),
+        DecodeError::Corrupt => (HTTPResponseStatus::BADREQUEST, "400 Bad Request"),
+    };
+    response.status = status;
+    response.body = Body::Text(body.to_string());
+    response.headers.insert("Content-Type", "text/plain");
+    response.send();
+}
+
+pub fn send_not_found(response: &mut Response) {
+    response.status = HTTPResponseStatus::NOTFOUND;
+    response.body = Body::Text("404 Not Found".to_string());
+    response.headers.insert("Content-Type", "text/plain");
+    response.send();
+}
+
+pub fn send_options(response: &mut Response, allowed: &[HTTPRequestMethod]) {
+    let allow: Vec<String> = allowed.iter().map(|method| method.to_string()).collect();
+    response.status = HTTPResponseStatus::NOCONTENT;
+    response.headers.insert("Allow", allow.join(", "));
+    response.send();
+}
+
+pub fn send_method_not_allowed(response: &mut Response, allowed: &[HTTPRequestMethod]) {
+    let allow: Vec<String> = allowed.iter().map(|method| method.to_string()).collect();
+    response.status = HTTPResponseStatus::METHODNOTALLOWED;
+    response.body = Body::Text("405 Method Not Allowed".to_string());
+    response.headers.insert("Content-Type", "text/plain");
+    response.headers.insert("Allow", allow.join(", "));
+    response.send();
+}
@@ -0,0 +1,752 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::conditional::{self, Validators};
+use crate::listing;
+use crate::mime::MimeTypes;
+use crate::range::{self, ByteRange, Ranges};
+use crate::request::{HTTPRequestMethod, Request};
+use crate::response::{Body, HTTPResponseStatus, Response};
+
+#[derive(Debug)]
+pub enum StaticError {
+    NotFound,
+    /// The path exists but is outside the root, or is not a regular file.
+    Forbidden,
+    Io(io::Error),
+}
+
+impl From<io::Error> for StaticError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => StaticError::NotFound,
+            io::ErrorKind::PermissionDenied => StaticError::Forbidden,
+            _ => StaticError::Io(error),
+        }
+    }
+}
+
+/// How requests for names starting with `.` are treated.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Dotfiles {
+    /// Served like any other file, and shown in listings.
+    Allow,
+    /// Answered with 403.
+    Deny,
+    /// Answered with 404 and left out of listings, as if they did not exist.
+    #[default]
+    Ignore,
+}
+
+/// Per-mount settings for a served directory.
+#[derive(Clone, Debug, Default)]
+pub struct ServeOptions {
+    pub mime_types: MimeTypes,
+    /// Sent as `Cache-Control` with every file, e.g. `public, max-age=3600`.
+    pub cache_control: Option<String>,
+    pub dotfiles: Dotfiles,
+    /// Serve a directory's `index.html`, when it has one.
+    pub index: bool,
+    /// List the contents of directories without an index.
+    pub listing: bool,
+}
+
+/// Serves files from beneath `root`. Request paths are percent-decoded, then
+/// resolved with every symlink followed, and anything that ends up outside
+/// the root is refused.
+///
+/// Directories are refused with 403 unless `index` or `listing` is on.
+#[derive(Clone, Debug)]
+pub struct StaticFiles {
+    root: PathBuf,
+    /// The URL path the root is mounted at, e.g. `/files/`, used to build
+    /// links in directory listings.
+    pub prefix: String,
+    pub options: ServeOptions,
+}
+
+impl StaticFiles {
+    pub fn new<P: Into<PathBuf>>(root: P) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            prefix: "/".to_string(),
+            options: ServeOptions::default(),
+        }
+    }
+
+    /// Maps a percent-encoded path relative to the root onto the file it
+    /// names. `..` segments are refused outright rather than resolved, and the
+    /// canonical result must still be inside the canonical root.
+    pub fn resolve(&self, relative: &str) -> Result<PathBuf, StaticError> {
+        let relative = StaticFiles::decode(relative)?;
+        let path = self.root.join(relative);
+        let root = fs::canonicalize(&self.root)?;
+        let path = fs::canonicalize(path)?;
+        if !path.starts_with(&root) {
+            return Err(StaticError::Forbidden);
+        }
+        Ok(path)
+    }
+
+    /// Maps a percent-encoded path onto where a file should be written, which
+    /// need not exist yet. The deepest part of the path that does exist must
+    /// resolve inside the root; anything below it is plain names, so
+    /// directories created for it stay inside too. The root itself, paths
+    /// that continue below a file, and hidden names, unless dotfiles are
+    /// allowed, are refused.
+    pub fn resolve_for_write(&self, relative: &str) -> Result<PathBuf, StaticError> {
+        self.check_dotfiles(relative)?;
+        let relative = StaticFiles::decode(relative)?;
+        if relative.as_os_str().is_empty() {
+            return Err(StaticError::Forbidden);
+        }
+
+        let root = fs::canonicalize(&self.root)?;
+        let path = root.join(&relative);
+        // `symlink_metadata` also finds dangling symlinks, which must not be
+        // skipped over and then written through.
+        let mut existing = path.as_path();
+        while fs::symlink_metadata(existing).is_err() {
+            existing = existing.parent().ok_or(StaticError::Forbidden)?;
+        }
+        let resolved = fs::canonicalize(existing).map_err(|_| StaticError::Forbidden)?;
+        if !resolved.starts_with(&root) {
+            return Err(StaticError::Forbidden);
+        }
+        let missing = path
+            .strip_prefix(existing)
+            .map_err(|_| StaticError::Forbidden)?;
+        // Joining an empty tail would add a trailing slash.
+        if missing.as_os_str().is_empty() {
+            return Ok(resolved);
+        }
+        if !resolved.is_dir() {
+            return Err(StaticError::Forbidden);
+        }
+        Ok(resolved.join(missing))
+    }
+
+    /// Maps a percent-encoded path onto a directory entry itself, for
+    /// removing it. Only the parent directory is resolved and confined to the
+    /// root, so a symlink names the link rather than its target. The entry
+    /// need not exist.
+    pub fn resolve_entry(&self, relative: &str) -> Result<PathBuf, StaticError> {
+        self.check_dotfiles(relative)?;
+        let relative = StaticFiles::decode(relative)?;
+        let name = relative.file_name().ok_or(StaticError::Forbidden)?;
+
+        let root = fs::canonicalize(&self.root)?;
+        let parent = root.join(relative.parent().unwrap_or(Path::new("")));
+        let parent = fs::canonicalize(parent)?;
+        if !parent.starts_with(&root) {
+            return Err(StaticError::Forbidden);
+        }
+        if !parent.is_dir() {
+            return Err(StaticError::NotFound);
+        }
+        Ok(parent.join(name))
+    }
+
+    /// Percent-decodes a request path and checks that every segment is a
+    /// plain name. Encoded separators such as `%2F` are decoded first, so
+    /// they are checked too.
+    fn decode(relative: &str) -> Result<PathBuf, StaticError> {
+        let decoded = urlencoding::decode(relative).map_err(|_| StaticError::NotFound)?;
+        if decoded.contains('\0') || decoded.contains('\\') {
+            return Err(StaticError::Forbidden);
+        }
+
+        let mut path = PathBuf::new();
+        for component in Path::new(decoded.as_ref()).components() {
+            match component {
+                Component::Normal(name) => path.push(name),
+                Component::CurDir | Component::RootDir => {}
+                Component::ParentDir | Component::Prefix(_) => return Err(StaticError::Forbidden),
+            }
+        }
+        Ok(path)
+    }
+
+    /// Sends the file at `relative` as raw bytes, streamed from disk, with its
+    /// `ETag` and `Last-Modified`. Conditional requests are answered with 304
+    /// or 412 where they apply. A `Range` header on a GET or HEAD selects part
+    /// of the file, unless an `If-Range` validator shows the client's copy is
+    /// out of date.
+    pub fn serve(&self, request: &Request, relative: &str, response: &mut Response) {
+        if let Err(error) = self.check_dotfiles(relative) {
+            return send_error(error, response);
+        }
+        let path = match self.resolve(relative) {
+            Ok(path) => path,
+            Err(error) => return send_error(error, response),
+        };
+        if path.is_dir() {
+            return self.serve_directory(request, relative, &path, response);
+        }
+        self.serve_file(request, &path, response);
+    }
+
+    fn check_dotfiles(&self, relative: &str) -> Result<(), StaticError> {
+        let hidden = StaticFiles::decode(relative)?
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+        match self.options.dotfiles {
+            _ if !hidden => Ok(()),
+            Dotfiles::Allow => Ok(()),
+            Dotfiles::Deny => Err(StaticError::Forbidden),
+            Dotfiles::Ignore => Err(StaticError::NotFound),
+        }
+    }
+
+    fn serve_directory(
+        &self,
+        request: &Request,
+        relative: &str,
+        path: &Path,
+        response: &mut Response,
+    ) {
+        if self.options.index && path.join("index.html").is_file() {
+            // Resolved again so an index that is a symlink is still confined.
+            match self.resolve(&format!("{}/index.html", relative)) {
+                Ok(index) => self.serve_file(request, &index, response),
+                Err(error) => send_error(error, response),
+            }
+            return;
+        }
+        if !self.options.listing {
+            return send_error(StaticError::Forbidden, response);
+        }
+
+        let relative = match StaticFiles::decode(relative) {
+            Ok(relative) => relative,
+            Err(error) => return send_error(error, response),
+        };
+        let show_hidden = self.options.dotfiles == Dotfiles::Allow;
+        let listed = listing::send(
+            request,
+            &self.prefix,
+            &relative,
+            path,
+            show_hidden,
+            response,
+        );
+        if let Err(error) = listed {
+            send_error(StaticError::from(error), response);
+        }
+    }
+
+    fn serve_file(&self, request: &Request, path: &Path, response: &mut Response) {
+        let file = match self.open(path) {
+            Ok(file) => file,
+            Err(error) => return send_error(error, response),
+        };
+
+        response.headers.insert("Accept-Ranges", "bytes");
+        if let Some(cache_control) = &self.options.cache_control {
+            response
+                .headers
+                .insert("Cache-Control", cache_control.clone());
+        }
+        file.validators.apply(response);
+        if let Some(status) = conditional::evaluate(request, &file.validators) {
+            return conditional::send_status(status, response);
+        }
+
+        let method = request.get_method();
+        let ranges = match request.headers.get("Range") {
+            Some(header)
+                if matches!(method, HTTPRequestMethod::GET | HTTPRequestMethod::HEAD)
+                    && if_range_matches(request, &file) =>
+            {
+                range::parse(header, file.length)
+            }
+            _ => None,
+        };
+
+        let sent = match ranges {
+            None => send_whole(file, response),
+            Some(Ranges::Unsatisfiable) => {
+                response.status = HTTPResponseStatus::RANGENOTSATISFIABLE;
+                response
+                    .headers
+                    .insert("Content-Range", format!("bytes */{}", file.length));
+                response.body = Body::Text("416 Range Not Satisfiable".to_string());
+                response.headers.insert("Content-Type", "text/plain");
+                response.send();
+                Ok(())
+            }
+            Some(Ranges::Satisfiable(ranges)) if ranges.len() == 1 => {
+                send_range(file, ranges[0], response)
+            }
+            Some(Ranges::Satisfiable(ranges)) => send_multipart(file, &ranges, response),
+        };
+        if let Err(error) = sent {
+            send_error(StaticError::from(error), response);
+        }
+    }
+
+    fn open(&self, path: &Path) -> Result<OpenFile, StaticError> {
+        let path = path.to_path_buf();
+        let metadata = fs::metadata(&path)?;
+        if !metadata.is_file() {
+            return Err(StaticError::Forbidden);
+        }
+        let mut file = File::open(&path)?;
+        let content_type = self.options.mime_types.content_type(&path, &mut file)?;
+        Ok(OpenFile {
+            path,
+            file,
+            length: metadata.len(),
+            validators: Validators::from_metadata(&metadata),
+            content_type,
+        })
+    }
+}
+
+struct OpenFile {
+    path: PathBuf,
+    file: File,
+    length: u64,
+    validators: Validators,
+    content_type: String,
+}
+
+/// Whether `If-Range`, if sent, still describes the file.
+fn if_range_matches(request: &Request, file: &OpenFile) -> bool {
+    match request.headers.get("If-Range") {
+        Some(validator) => file.validators.matches_if_range(validator),
+        None => true,
+    }
+}
+
+fn send_whole(file: OpenFile, response: &mut Response) -> io::Result<()> {
+    response.status = HTTPResponseStatus::OK;
+    response.headers.insert("Content-Type", file.content_type);
+    response
+        .headers
+        .insert("Content-Length", file.length.to_string());
+    response.body = Body::stream(file.file);
+    response.send();
+    Ok(())
+}
+
+fn send_range(mut file: OpenFile, range: ByteRange, response: &mut Response) -> io::Result<()> {
+    file.file.seek(SeekFrom::Start(range.start))?;
+    response.status = HTTPResponseStatus::PARTIALCONTENT;
+    response.headers.insert("Content-Type", file.content_type);
+    response
+        .headers
+        .insert("Content-Range", range.content_range(file.length));
+    response
+        .headers
+        .insert("Content-Length", range.len().to_string());
+    response.body = Body::stream(file.file.take(range.len()));
+    response.send();
+    Ok(())
+}
+
+/// Sends several ranges as a `multipart/byteranges` body. Each part reads
+/// through its own handle on the file, so the parts can be streamed one
+/// after another.
+fn send_multipart(file: OpenFile, ranges: &[ByteRange], response: &mut Response) -> io::Result<()> {
+    let boundary = boundary();
+    let mut length = 0;
+    let mut body: Box<dyn Read + Send> = Box::new(io::empty());
+    for (index, range) in ranges.iter().enumerate() {
+        let mut part = File::open(&file.path)?;
+        part.seek(SeekFrom::Start(range.start))?;
+        let separator = if index == 0 { "" } else { "\r\n" };
+        let head = format!(
+            "{}--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            separator,
+            boundary,
+            file.content_type,
+            range.content_range(file.length)
+        );
+        length += head.len() as u64 + range.len();
+        body = Box::new(
+            body.chain(io::Cursor::new(head))
+                .chain(part.take(range.len())),
+        );
+    }
+    let end = format!("\r\n--{}--\r\n", boundary);
+    length += end.len() as u64;
+    body = Box::new(body.chain(io::Cursor::new(end)));
+
+    response.status = HTTPResponseStatus::PARTIALCONTENT;
+    response.headers.insert(
+        "Content-Type",
+        format!("multipart/byteranges; boundary={}", boundary),
+    );
+    response
+        .headers
+        .insert("Content-Length", length.to_string());
+    response.body = Body::Stream(body);
+    response.send();
+    Ok(())
+}
+
+/// A multipart boundary that is vanishingly unlikely to appear in the file.
+fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    format!("byteranges-{:x}{:x}", nanos, std::process::id())
+}
+
+pub fn send_error(error: StaticError, response: &mut Response) {
+    let (status, body) = match error {
+        StaticError::NotFound => (HTTPResponseStatus::NOTFOUND, "404 Not Found"),
+        StaticError::Forbidden => (HTTPResponseStatus::FORBIDDEN, "403 Forbidden"),
+        StaticError::Io(e) => {
+            println!("error: {}", e);
+            (
+                HTTPResponseStatus::INTERNALSERVERERROR,
+                "500 Internal Server Error",
+            )
+        }
+    };
+    response.status = status;
+    response.body = Body::Text(body.to_string());
+    response.headers.insert("Content-Type", "text/plain");
+    response.send();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::os::unix::fs::symlink;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A scratch directory holding `root/` to serve and `outside/` beside it,
+    /// removed when dropped.
+    struct Scratch {
+        base: PathBuf,
+    }
+
+    impl Scratch {
+        fn new() -> Scratch {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let base = std::env::temp_dir().join(format!(
+                "static-files-test-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(base.join("root/sub")).unwrap();
+            fs::create_dir_all(base.join("outside")).unwrap();
+            fs::write(base.join("root/a.txt"), "a").unwrap();
+            fs::write(base.join("root/sub/b.txt"), "b").unwrap();
+            fs::write(base.join("outside/secret.txt"), "secret").unwrap();
+            Scratch {
+                base: fs::canonicalize(base).unwrap(),
+            }
+        }
+
+        fn root(&self) -> PathBuf {
+            self.base.join("root")
+        }
+
+        fn files(&self) -> StaticFiles {
+            StaticFiles::new(self.root())
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[test]
+    fn resolves_files_inside_the_root() {
+        let scratch = Scratch::new();
+        let files = scratch.files();
+        assert_eq!(
+            files.resolve("a.txt").unwrap(),
+            scratch.root().join("a.txt")
+        );
+        assert_eq!(
+            files.resolve("/sub/./b.txt").unwrap(),
+            scratch.root().join("sub/b.txt")
+        );
+        assert_eq!(
+            files.resolve("sub%2Fb.txt").unwrap(),
+            scratch.root().join("sub/b.txt")
+        );
+        assert!(matches!(
+            files.resolve("missing.txt"),
+            Err(StaticError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn refuses_parent_segments() {
+        let scratch = Scratch::new();
+        let files = scratch.files();
+        for relative in [
+            "..",
+            "../outside/secret.txt",
+            "sub/../a.txt",
+            "%2e%2e%2foutside%2fsecret.txt",
+            "%2E%2E/outside/secret.txt",
+            "..%2Foutside%2Fsecret.txt",
+        ] {
+            assert!(
+                matches!(files.resolve(relative), Err(StaticError::Forbidden)),
+                "{}",
+                relative
+            );
+            assert!(
+                matches!(
+                    files.resolve_for_write(relative),
+                    Err(StaticError::Forbidden)
+                ),
+                "{}",
+                relative
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_backslashes_and_nul() {
+        let scratch = Scratch::new();
+        let files = scratch.files();
+        for relative in [
+            "..\\outside\\secret.txt",
+            "sub%5Cb.txt",
+            "a.txt%00",
+            "a.txt%00.png",
+        ] {
+            assert!(
+                matches!(files.resolve(relative), Err(StaticError::Forbidden)),
+                "{}",
+                relative
+            );
+            assert!(
+                matches!(
+                    files.resolve_for_write(relative),
+                    Err(StaticError::Forbidden)
+                ),
+                "{}",
+                relative
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_symlinks_that_escape_the_root() {
+        let scratch = Scratch::new();
+        symlink(scratch.base.join("outside"), scratch.root().join("out")).unwrap();
+        symlink(
+            scratch.base.join("outside/secret.txt"),
+            scratch.root().join("secret.txt"),
+        )
+        .unwrap();
+        let files = scratch.files();
+        for relative in ["out", "out/secret.txt", "secret.txt"] {
+            assert!(
+                matches!(files.resolve(relative), Err(StaticError::Forbidden)),
+                "{}",
+                relative
+            );
+        }
+        for relative in ["out/new.txt", "secret.txt", "out/deeper/new.txt"] {
+            assert!(
+                matches!(
+                    files.resolve_for_write(relative),
+                    Err(StaticError::Forbidden)
+                ),
+                "{}",
+                relative
+            );
+        }
+    }
+
+    #[test]
+    fn follows_symlinks_that_stay_inside() {
+        let scratch = Scratch::new();
+        symlink(scratch.root().join("sub"), scratch.root().join("alias")).unwrap();
+        let files = scratch.files();
+        assert_eq!(
+            files.resolve("alias/b.txt").unwrap(),
+            scratch.root().join("sub/b.txt")
+        );
+        assert_eq!(
+            files.resolve_for_write("alias/new.txt").unwrap(),
+            scratch.root().join("sub/new.txt")
+        );
+    }
+
+    #[test]
+    fn refuses_dangling_symlinks() {
+        let scratch = Scratch::new();
+        symlink(
+            scratch.base.join("outside/nowhere.txt"),
+            scratch.root().join("dangling"),
+        )
+        .unwrap();
+        let files = scratch.files();
+        assert!(matches!(
+            files.resolve("dangling"),
+            Err(StaticError::NotFound)
+        ));
+        assert!(matches!(
+            files.resolve_for_write("dangling"),
+            Err(StaticError::Forbidden)
+        ));
+        assert!(matches!(
+            files.resolve_for_write("dangling/new.txt"),
+            Err(StaticError::Forbidden)
+        ));
+    }
+
+    #[test]
+    fn resolves_new_paths_for_writes() {
+        let scratch = Scratch::new();
+        let files = scratch.files();
+        assert_eq!(
+            files.resolve_for_write("a.txt").unwrap(),
+            scratch.root().join("a.txt")
+        );
+        assert_eq!(
+            files.resolve_for_write("new/dir/c.txt").unwrap(),
+            scratch.root().join("new/dir/c.txt")
+        );
+        assert_eq!(
+            files.resolve_for_write("sub/c%20d.txt").unwrap(),
+            scratch.root().join("sub/c d.txt")
+        );
+    }
+
+    #[test]
+    fn refuses_writes_below_a_regular_file() {
+        let scratch = Scratch::new();
+        let files = scratch.files();
+        for relative in ["a.txt/b", "a.txt/b/c", "sub/b.txt/c"] {
+            assert!(
+                matches!(
+                    files.resolve_for_write(relative),
+                    Err(StaticError::Forbidden)
+                ),
+                "{}",
+                relative
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_writes_to_the_root_itself() {
+        let scratch = Scratch::new();
+        let files = scratch.files();
+        for relative in ["", "/", ".", "%2F", "./"] {
+            assert!(
+                matches!(
+                    files.resolve_for_write(relative),
+                    Err(StaticError::Forbidden)
+                ),
+                "{:?}",
+                relative
+            );
+        }
+    }
+
+    #[test]
+    fn applies_the_dotfile_policy_to_writes() {
+        let scratch = Scratch::new();
+        let mut files = scratch.files();
+        assert!(matches!(
+            files.resolve_for_write(".env"),
+            Err(StaticError::NotFound)
+        ));
+        files.options.dotfiles = Dotfiles::Deny;
+        assert!(matches!(
+            files.resolve_for_write("sub/.git/config"),
+            Err(StaticError::Forbidden)
+        ));
+        files.options.dotfiles = Dotfiles::Allow;
+        assert_eq!(
+            files.resolve_for_write(".env").unwrap(),
+            scratch.root().join(".env")
+        );
+    }
+
+    #[test]
+    fn resolves_entries_without_following_them() {
+        let scratch = Scratch::new();
+        symlink(
+            scratch.root().join("a.txt"),
+            scratch.root().join("link.txt"),
+        )
+        .unwrap();
+        symlink(
+            scratch.base.join("outside/secret.txt"),
+            scratch.root().join("secret.txt"),
+        )
+        .unwrap();
+        symlink(
+            scratch.base.join("outside/nowhere"),
+            scratch.root().join("dangling"),
+        )
+        .unwrap();
+        let files = scratch.files();
+        for relative in [
+            "link.txt",
+            "secret.txt",
+            "dangling",
+            "sub/b.txt",
+            "missing.txt",
+        ] {
+            assert_eq!(
+                files.resolve_entry(relative).unwrap(),
+                scratch.root().join(relative)
+            );
+        }
+    }
+
+    #[test]
+    fn confines_the_parent_of_entries() {
+        let scratch = Scratch::new();
+        symlink(scratch.base.join("outside"), scratch.root().join("out")).unwrap();
+        symlink(scratch.root().join("sub"), scratch.root().join("alias")).unwrap();
+        let files = scratch.files();
+        assert_eq!(
+            files.resolve_entry("alias/b.txt").unwrap(),
+            scratch.root().join("sub/b.txt")
+        );
+        for relative in [
+            "out/secret.txt",
+            "../outside/secret.txt",
+            "",
+            "/",
+            "a.txt%00",
+        ] {
+            assert!(
+                matches!(files.resolve_entry(relative), Err(StaticError::Forbidden)),
+                "{:?}",
+                relative
+            );
+        }
+        for relative in ["missing/a.txt", "a.txt/b", ".env"] {
+            assert!(
+                matches!(files.resolve_entry(relative), Err(StaticError::NotFound)),
+                "{}",
+                relative
+            );
+        }
+    }
+
+    #[test]
+    fn reports_a_missing_root() {
+        let scratch = Scratch::new();
+        let files = StaticFiles::new(scratch.base.join("gone"));
+        assert!(matches!(files.resolve("a.txt"), Err(StaticError::NotFound)));
+        assert!(matches!(
+            files.resolve_for_write("a.txt"),
+            Err(StaticError::NotFound)
+        ));
+    }
+}